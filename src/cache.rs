@@ -0,0 +1,183 @@
+use crate::hash_file;
+use serde::Serialize;
+use sha2::{Digest, Sha512};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use std::{env, fs};
+
+/// modpm's on-disk cache: jars stored by their sha512, and Modrinth API responses stored by URL.
+pub struct Cache {}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CacheInfo {
+    pub directory: String,
+    pub files: u64,
+    pub files_size: u64,
+    pub responses: u64,
+    pub responses_size: u64,
+}
+
+impl Cache {
+    pub fn get_directory() -> String {
+        if let Ok(cache_dir) = env::var("MODPM_CACHE_DIR") {
+            return cache_dir;
+        }
+
+        match std::env::consts::OS {
+            "macos" => format!(
+                "{}/Library/Caches/modpm",
+                env::var("HOME").expect("Couldn't get the $HOME env var.")
+            ),
+            "windows" => format!(
+                "{}\\modpm\\cache",
+                env::var("LOCALAPPDATA").expect("Couldn't get the $LOCALAPPDATA env var.")
+            ),
+            _ => match env::var("XDG_CACHE_HOME") {
+                Ok(cache_home) => format!("{}/modpm", cache_home),
+                Err(_) => format!(
+                    "{}/.cache/modpm",
+                    env::var("HOME").expect("Couldn't get the $HOME env var.")
+                ),
+            },
+        }
+    }
+
+    /// Where a file with this hash lives in the cache. Hashes come from lockfiles and packs, so
+    /// anything that isn't a real sha512 is refused before it gets anywhere near a path.
+    fn file_path(sha512: &str) -> Option<String> {
+        if !is_sha512(sha512) {
+            return None;
+        }
+
+        Some(format!(
+            "{}/files/{}/{}",
+            Cache::get_directory(),
+            &sha512[..2],
+            sha512
+        ))
+    }
+
+    fn response_path(url: &str) -> String {
+        let key = hex::encode(Sha512::digest(url.as_bytes()));
+        format!("{}/api/{}.json", Cache::get_directory(), &key[..32])
+    }
+
+    pub fn has_file(sha512: &str) -> bool {
+        Cache::file_path(sha512).is_some_and(|path| Path::new(&path).exists())
+    }
+
+    /// Puts a cached file at `destination`, hard linking it if possible. Returns false if it isn't cached.
+    pub fn link_file(sha512: &str, destination: &str) -> bool {
+        let cached = match Cache::file_path(sha512) {
+            Some(cached) if Path::new(&cached).exists() => cached,
+            _ => return false,
+        };
+        // hard links share their contents, so something editing a mod in one instance edits the
+        // cached copy too - don't hand that out again
+        if hash_file(&cached).ok().as_deref() != Some(sha512) {
+            fs::remove_file(&cached).ok();
+            return false;
+        }
+
+        fs::remove_file(destination).ok();
+        fs::hard_link(&cached, destination).is_ok() || fs::copy(&cached, destination).is_ok()
+    }
+
+    /// Copies a downloaded file into the cache, as long as it really has the hash it should.
+    pub fn store_file(path: &str, sha512: &str) {
+        let cached = match Cache::file_path(sha512) {
+            Some(cached) => cached,
+            None => return,
+        };
+        if Path::new(&cached).exists() || hash_file(path).ok().as_deref() != Some(sha512) {
+            return;
+        }
+
+        if let Some(parent) = Path::new(&cached).parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        fs::copy(path, &cached).ok();
+    }
+
+    /// A cached response for `url`, if there is one newer than `ttl`. No `ttl` means any age will do.
+    pub fn get_response(url: &str, ttl: Option<Duration>) -> Option<String> {
+        let path = Cache::response_path(url);
+
+        if let Some(ttl) = ttl {
+            let modified = fs::metadata(&path).ok()?.modified().ok()?;
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age > ttl {
+                return None;
+            }
+        }
+
+        fs::read_to_string(path).ok()
+    }
+
+    pub fn store_response(url: &str, body: &str) {
+        let path = Cache::response_path(url);
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent).ok();
+        }
+        fs::write(path, body).ok();
+    }
+
+    pub fn info() -> CacheInfo {
+        let directory = Cache::get_directory();
+        let (files, files_size) = dir_stats(&format!("{}/files", directory));
+        let (responses, responses_size) = dir_stats(&format!("{}/api", directory));
+
+        CacheInfo {
+            directory,
+            files,
+            files_size,
+            responses,
+            responses_size,
+        }
+    }
+
+    pub fn clean(files: bool, responses: bool) {
+        let directory = Cache::get_directory();
+        if files {
+            fs::remove_dir_all(format!("{}/files", directory)).ok();
+        }
+        if responses {
+            fs::remove_dir_all(format!("{}/api", directory)).ok();
+        }
+    }
+}
+
+/// Whether `hash` looks like a sha512 as Modrinth writes them: 128 lowercase hex characters.
+fn is_sha512(hash: &str) -> bool {
+    hash.len() == 128
+        && hash
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+/// How many files are under a folder, and how big they are altogether.
+fn dir_stats(path: &str) -> (u64, u64) {
+    let mut count = 0;
+    let mut size = 0;
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                let (sub_count, sub_size) = dir_stats(&entry.path().to_string_lossy());
+                count += sub_count;
+                size += sub_size;
+            } else {
+                count += 1;
+                size += metadata.len();
+            }
+        }
+    }
+
+    (count, size)
+}
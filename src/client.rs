@@ -0,0 +1,93 @@
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::http::{self, HttpError};
+use std::env;
+use std::time::Duration;
+
+pub const DEFAULT_API_BASE: &str = "https://api.modrinth.com/v2";
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+// project and version info barely changes, unlike search results
+const CACHED_PATHS: [&str; 5] = [
+    "project/",
+    "projects",
+    "version/",
+    "versions",
+    "version_file/",
+];
+
+/// Talks to the Modrinth API, wherever it happens to be hosted.
+#[derive(Debug, Clone)]
+pub struct ModrinthClient {
+    pub base_url: String,
+    /// How long cached API responses are used for while online. `None` means always asking the
+    /// API, though responses are still stored for `--offline`.
+    pub cache_ttl: Option<Duration>,
+}
+
+impl ModrinthClient {
+    pub fn new(base_url: &str) -> ModrinthClient {
+        ModrinthClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache_ttl: Some(DEFAULT_CACHE_TTL),
+        }
+    }
+
+    /// Uses `$MODPM_API_BASE`, then the config file, then the real Modrinth API.
+    pub fn from_config() -> ModrinthClient {
+        let config = Config::load();
+        let base_url = match env::var("MODPM_API_BASE") {
+            Ok(base_url) => base_url,
+            Err(_) => config
+                .api_base
+                .unwrap_or_else(|| DEFAULT_API_BASE.to_string()),
+        };
+
+        let mut client = ModrinthClient::new(&base_url);
+        if let Some(ttl) = config.cache_ttl {
+            client.cache_ttl = match ttl {
+                0 => None,
+                ttl => Some(Duration::from_secs(ttl)),
+            };
+        }
+
+        client
+    }
+
+    /// The full URL for an API path like `project/sodium`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    /// GETs an API path, going through the cache for project and version info.
+    pub async fn get(&self, path: &str) -> Result<String, HttpError> {
+        let url = self.url(path);
+        let cached_path = CACHED_PATHS.iter().any(|p| path.starts_with(p));
+
+        // offline, an old answer beats no answer, whatever the TTL says
+        if http::is_offline() && cached_path {
+            if let Some(body) = Cache::get_response(&url, None) {
+                return Ok(body);
+            }
+        }
+
+        if let (Some(ttl), true) = (self.cache_ttl, cached_path) {
+            if let Some(body) = Cache::get_response(&url, Some(ttl)) {
+                return Ok(body);
+            }
+        }
+
+        let body = http::get(&url)
+            .await?
+            .text()
+            .await
+            .map_err(HttpError::Request)?;
+
+        // stored even when the TTL says not to use it, so --offline has something to go on
+        if cached_path {
+            Cache::store_response(&url, &body);
+        }
+
+        Ok(body)
+    }
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+/// Settings from modpm's config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    /// Where the Modrinth API lives, e.g. a mirror or staging. Overridden by `$MODPM_API_BASE`.
+    pub api_base: Option<String>,
+    /// How many seconds cached Modrinth API responses are used for. 0 means always asking the API.
+    pub cache_ttl: Option<u64>,
+    /// Where the launcher's metadata lives, for picking modloader versions. Overridden by
+    /// `$MODPM_META_BASE`.
+    pub meta_base: Option<String>,
+}
+
+impl Config {
+    pub fn get_directory() -> String {
+        match std::env::consts::OS {
+            "macos" => format!(
+                "{}/Library/Application Support/modpm",
+                env::var("HOME").expect("Couldn't get the $HOME env var.")
+            ),
+            "windows" => format!(
+                "{}\\modpm",
+                env::var("APPDATA").expect("Couldn't get the $APPDATA env var.")
+            ),
+            _ => match env::var("XDG_CONFIG_HOME") {
+                Ok(config_home) => format!("{}/modpm", config_home),
+                Err(_) => format!(
+                    "{}/.config/modpm",
+                    env::var("HOME").expect("Couldn't get the $HOME env var.")
+                ),
+            },
+        }
+    }
+
+    pub fn get_path() -> String {
+        format!("{}/config.json", Config::get_directory())
+    }
+
+    /// Reads the config file, falling back to the defaults if there isn't one.
+    pub fn load() -> Config {
+        match fs::read_to_string(Config::get_path()) {
+            Ok(config_string) => {
+                json5::from_str(&config_string[..]).expect("Couldn't parse the modpm config file")
+            }
+            Err(_) => Config::default(),
+        }
+    }
+}
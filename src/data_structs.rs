@@ -1,89 +1,465 @@
-use crate::modrinth::{ModVersionFile, MpmMod};
-use crate::polymc::PolyMC;
+use crate::crash;
+use crate::modrinth::{
+    default_project_type, ModVersionDependencies, ModVersionFile, ModVersionFileHashes,
+};
 use crate::{modrinth::ModVersion, PolyInstance};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, File},
-    io::ErrorKind,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, Write},
+    path::Path,
 };
 
 pub struct ModpmLockfile {}
 
+/// What's written to `.modpm_lockfile.json`. Bump `LOCKFILE_SCHEMA_VERSION` whenever the format
+/// changes, and teach `migrate` how to read the old one.
 #[derive(Serialize, Deserialize, Debug)]
+struct Lockfile {
+    schema_version: u32,
+    mods: Vec<LockfileMod>,
+}
+
+/// Version 0 was a bare array of these, with the whole Modrinth version (and sometimes project) in each.
+#[derive(Deserialize)]
+struct LockfileModV0 {
+    version: ModVersion,
+    file: ModVersionFile,
+    #[serde(default)]
+    dependency_of: Option<String>,
+    #[serde(default = "default_project_type")]
+    project_type: String,
+    #[serde(default = "default_directory")]
+    directory: String,
+}
+
+pub const LOCKFILE_SCHEMA_VERSION: u32 = 1;
+
+tokio::task_local! {
+    /// The `.modpm.lock` files the current task is holding for a whole command. Locking the same
+    /// file again from inside that command would wait on itself forever, but other tasks and
+    /// threads aren't part of it and have to wait their turn.
+    static HELD_LOCKS: Vec<String>;
+}
+
+fn holds_lock(path: &str) -> bool {
+    HELD_LOCKS
+        .try_with(|held| held.iter().any(|p| p == path))
+        .unwrap_or(false)
+}
+
+/// One installed file, with just enough about its version to check it, update it, and draw the tree.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct LockfileMod {
-    pub version: ModVersion,
-    pub file: ModVersionFile,
-    pub mpm_mod: Option<MpmMod>,
+    pub project_id: String,
+    pub version_id: String,
+    pub version_number: String,
+    /// When the version came out, so updates can tell what's newer.
+    pub date_published: String,
+    pub filename: String,
+    pub url: String,
+    pub hashes: ModVersionFileHashes,
+    pub reason: InstallReason,
+    #[serde(default = "default_project_type")]
+    pub project_type: String,
+    /// Where the file lives, relative to the instance's `.minecraft` folder.
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<ModVersionDependencies>,
+    /// Disabled the way PolyMC does it, by putting `.disabled` on the end of the filename.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+}
+
+/// Why a mod is in the instance.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallReason {
+    /// Someone asked for it.
+    Requested,
+    /// It was pulled in by the mod with this project id.
+    DependencyOf(String),
+}
+
+impl LockfileMod {
+    pub fn new(
+        version: &ModVersion,
+        file: &ModVersionFile,
+        project_type: &str,
+        directory: &str,
+        dependency_of: Option<String>,
+    ) -> LockfileMod {
+        LockfileMod {
+            project_id: version.project_id.clone(),
+            version_id: version.id.clone(),
+            version_number: version.version_number.clone(),
+            date_published: version.date_published.clone(),
+            filename: file.filename.clone(),
+            url: file.url.clone(),
+            hashes: file.hashes.clone(),
+            reason: match dependency_of {
+                Some(parent) => InstallReason::DependencyOf(parent),
+                None => InstallReason::Requested,
+            },
+            project_type: project_type.to_string(),
+            directory: directory.to_string(),
+            dependencies: version.dependencies.clone(),
+            disabled: false,
+        }
+    }
+
+    /// Where the file actually is, relative to `.minecraft`, taking disabling into account.
+    pub fn relative_path(&self) -> String {
+        format!(
+            "{}/{}{}",
+            self.directory,
+            self.filename,
+            if self.disabled { ".disabled" } else { "" }
+        )
+    }
+
+    /// The project id of the mod this was installed as a dependency of.
+    pub fn dependency_of(&self) -> Option<&str> {
+        match &self.reason {
+            InstallReason::DependencyOf(parent) => Some(parent),
+            InstallReason::Requested => None,
+        }
+    }
+
+    pub fn time(&self) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(&self.date_published)
+            .map(|t| t.timestamp())
+            .unwrap_or_default()
+    }
+}
+
+fn default_directory() -> String {
+    "mods".to_string()
+}
+
+pub(crate) fn installed_files_path(instance: &PolyInstance) -> String {
+    format!(
+        "{}/mods/.modpm_installed.json",
+        instance.minecraft_directory()
+    )
+}
+
+fn save_installed_files(instance: &PolyInstance, files: &[String]) {
+    write_atomically(
+        &installed_files_path(instance),
+        &json5::to_string(&files).expect("Couldn't serialize the installed files"),
+    )
+    .expect("Couldn't save the installed files");
+}
+
+/// Reads any lockfile modpm has ever written. Old ones are converted in memory, and only get
+/// rewritten in the new format the next time something changes.
+fn migrate(text: &str) -> Result<Vec<LockfileMod>, String> {
+    let value: serde_json::Value =
+        json5::from_str(text).map_err(|e| format!("Couldn't read the lockfile: {}", e))?;
+
+    if value.is_array() {
+        let old: Vec<LockfileModV0> = serde_json::from_value(value)
+            .map_err(|e| format!("Couldn't read the old lockfile: {}", e))?;
+        return Ok(old
+            .into_iter()
+            .map(|m| {
+                LockfileMod::new(
+                    &m.version,
+                    &m.file,
+                    &m.project_type,
+                    &m.directory,
+                    m.dependency_of,
+                )
+            })
+            .collect());
+    }
+
+    let schema_version = value["schema_version"].as_u64().unwrap_or_default();
+    if schema_version > LOCKFILE_SCHEMA_VERSION as u64 {
+        return Err(format!(
+            "This lockfile was made by a newer version of modpm (schema {}, I only know up to {}). Please update modpm.",
+            schema_version, LOCKFILE_SCHEMA_VERSION
+        ));
+    }
+
+    let lockfile: Lockfile =
+        serde_json::from_value(value).map_err(|e| format!("Couldn't read the lockfile: {}", e))?;
+    Ok(lockfile.mods)
 }
 
 impl ModpmLockfile {
-    pub fn add_to_lockfile(instance: PolyInstance, version: &ModVersion, file: &ModVersionFile) {
-        let mut current_lockfile = ModpmLockfile::get_lockfile(instance.clone());
-
-        current_lockfile.push(LockfileMod {
-            version: version.clone(),
-            file: file.clone(),
-            mpm_mod: None,
-        });
-
-        let new_lockfile_string =
-            json5::to_string(&current_lockfile).expect("Couldn't serialize a lockfile");
-
-        let result = fs::write(
-            format!(
-                "{}/instances/{}/.minecraft/mods/.modpm_lockfile.json",
-                PolyMC::get_directory(),
-                instance.folder_name
-            ),
-            &new_lockfile_string,
-        );
-
-        match result {
-            Ok(_) => {}
-            Err(error) => {
-                if error.kind() == ErrorKind::NotFound {
-                    File::create(format!(
-                        "{}/instances/{}/.minecraft/mods/.modpm_lockfile.json",
-                        PolyMC::get_directory(),
-                        instance.folder_name
+    /// Adds a mod, replacing any entry that was there for its project. Gives back the one it replaced.
+    pub fn add_to_lockfile(
+        instance: PolyInstance,
+        version: &ModVersion,
+        file: &ModVersionFile,
+        project_type: &str,
+        directory: &str,
+        dependency_of: Option<String>,
+    ) -> Option<LockfileMod> {
+        let _lock = ModpmLockfile::lock(&instance);
+        let mut current_lockfile =
+            ModpmLockfile::get_lockfile(instance.clone()).unwrap_or_else(|e| crash(e));
+
+        // a project only has one entry, so a new version replaces the old one in the same write
+        let old = current_lockfile
+            .iter()
+            .position(|m| m.project_id == version.project_id)
+            .map(|position| current_lockfile.remove(position));
+        if let Some(old) = &old {
+            ModpmLockfile::untrack_file(&instance, &old.relative_path());
+        }
+
+        ModpmLockfile::track_file(&instance, &format!("{}/{}", directory, file.filename));
+
+        current_lockfile.push(LockfileMod::new(
+            version,
+            file,
+            project_type,
+            directory,
+            dependency_of,
+        ));
+
+        ModpmLockfile::save(&instance, &current_lockfile);
+
+        old
+    }
+
+    /// Takes a project out of the lockfile, giving back the entry that was removed.
+    pub fn remove_from_lockfile(instance: PolyInstance, project_id: &str) -> Option<LockfileMod> {
+        let _lock = ModpmLockfile::lock(&instance);
+        let mut current_lockfile =
+            ModpmLockfile::get_lockfile(instance.clone()).unwrap_or_else(|e| crash(e));
+
+        let position = current_lockfile
+            .iter()
+            .position(|m| m.project_id == project_id)?;
+        let removed = current_lockfile.remove(position);
+        ModpmLockfile::untrack_file(&instance, &removed.relative_path());
+
+        ModpmLockfile::save(&instance, &current_lockfile);
+
+        Some(removed)
+    }
+
+    /// Finds an installed mod by project id, filename, or the start of its filename.
+    pub fn find_mod(instance: &PolyInstance, query: &str) -> Result<LockfileMod, String> {
+        let lockfile = ModpmLockfile::get_lockfile(instance.clone())?;
+        let query_lower = query.to_lowercase();
+
+        let candidates: [Vec<&LockfileMod>; 3] = [
+            lockfile.iter().filter(|m| m.project_id == query).collect(),
+            lockfile
+                .iter()
+                .filter(|m| m.filename.to_lowercase() == query_lower)
+                .collect(),
+            lockfile
+                .iter()
+                .filter(|m| m.filename.to_lowercase().starts_with(&query_lower))
+                .collect(),
+        ];
+
+        for found in candidates {
+            match found.len() {
+                0 => continue,
+                1 => return Ok(found[0].clone()),
+                _ => {
+                    return Err(format!(
+                        "{} could mean any of: {}",
+                        query,
+                        found
+                            .iter()
+                            .map(|m| m.filename.clone())
+                            .collect::<Vec<String>>()
+                            .join(", ")
                     ))
-                    .expect("Couldn't create a lockfile");
-                    fs::write(
-                        format!(
-                            "{}/{}/.minecraft/mods/.modpm_lockfile.json",
-                            PolyMC::get_directory(),
-                            instance.folder_name
-                        ),
-                        new_lockfile_string,
-                    )
-                    .expect("something went really really wrong while making a lockfile");
-                } else {
-                    panic!("something went really really wrong while making a lockfile")
-                };
+                }
             }
         }
+
+        Err(format!("{} isn't installed in this instance.", query))
     }
 
-    pub fn get_lockfile(instance: PolyInstance) -> Vec<LockfileMod> {
-        let mut current_lockfile_string = "".to_string();
-        let possible_lockfile_string = &fs::read_to_string(format!(
-            "{}/instances/{}/.minecraft/mods/.modpm_lockfile.json",
-            PolyMC::get_directory(),
-            instance.folder_name
-        ));
-        match possible_lockfile_string {
-            Ok(_) => {
-                possible_lockfile_string.as_ref().expect("wtf")[..]
-                    .clone_into(&mut current_lockfile_string);
-            }
-            Err(_) => {
-                "[]".clone_into(&mut current_lockfile_string);
-            }
+    /// Renames a mod's file to or from `.disabled` and records it, giving back the updated entry.
+    /// If the file can't be renamed, the lockfile is left as it was.
+    pub fn set_disabled(
+        instance: &PolyInstance,
+        project_id: &str,
+        disabled: bool,
+    ) -> Result<LockfileMod, String> {
+        let _lock = ModpmLockfile::lock(instance);
+        let mut current_lockfile = ModpmLockfile::get_lockfile(instance.clone())?;
+
+        let entry = current_lockfile
+            .iter_mut()
+            .find(|m| m.project_id == project_id)
+            .ok_or_else(|| format!("{} isn't installed in this instance.", project_id))?;
+        let old_path = entry.relative_path();
+        entry.disabled = disabled;
+        let new_path = entry.relative_path();
+        let updated = entry.clone();
+
+        if old_path != new_path {
+            let minecraft_directory = instance.minecraft_directory();
+            fs::rename(
+                format!("{}/{}", minecraft_directory, old_path),
+                format!("{}/{}", minecraft_directory, new_path),
+            )
+            .map_err(|e| format!("Couldn't rename {} to {}: {}", old_path, new_path, e))?;
+            ModpmLockfile::untrack_file(instance, &old_path);
+            ModpmLockfile::track_file(instance, &new_path);
+        }
+
+        ModpmLockfile::save(instance, &current_lockfile);
+
+        Ok(updated)
+    }
+
+    /// Every file modpm has put in an instance, relative to `.minecraft`. This stays on the
+    /// machine (unlike the lockfile), and it's how `sync` knows which files it's allowed to delete.
+    pub fn get_installed_files(instance: &PolyInstance) -> Vec<String> {
+        match fs::read_to_string(installed_files_path(instance)) {
+            Ok(text) => json5::from_str(&text).expect("Couldn't deserialize the installed files"),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn set_installed_files(instance: &PolyInstance, files: &[String]) {
+        let _lock = ModpmLockfile::lock(instance);
+        save_installed_files(instance, files);
+    }
+
+    fn track_file(instance: &PolyInstance, file: &str) {
+        let mut files = ModpmLockfile::get_installed_files(instance);
+        if !files.iter().any(|f| f == file) {
+            files.push(file.to_string());
+            save_installed_files(instance, &files);
+        }
+    }
+
+    fn untrack_file(instance: &PolyInstance, file: &str) {
+        let mut files = ModpmLockfile::get_installed_files(instance);
+        files.retain(|f| f != file);
+        save_installed_files(instance, &files);
+    }
+
+    /// Where an instance's lockfile lives. Everything that touches it should go through this.
+    pub fn get_path(instance: &PolyInstance) -> String {
+        format!(
+            "{}/mods/.modpm_lockfile.json",
+            instance.minecraft_directory()
+        )
+    }
+
+    /// Replaces the whole lockfile. Pretty printed, so it diffs nicely in git.
+    pub fn write_lockfile(instance: PolyInstance, lockfile: &[LockfileMod]) {
+        let _lock = ModpmLockfile::lock(&instance);
+        ModpmLockfile::save(&instance, lockfile);
+    }
+
+    /// Reads a lockfile that isn't in an instance, like an old one out of git.
+    pub fn read_lockfile_file(path: &str) -> Result<Vec<LockfileMod>, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        ModpmLockfile::parse_lockfile(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse_lockfile(text: &str) -> Result<Vec<LockfileMod>, String> {
+        migrate(text)
+    }
+
+    /// The instance's lockfile, which is empty if there isn't one yet. An error names the file.
+    pub fn get_lockfile(instance: PolyInstance) -> Result<Vec<LockfileMod>, String> {
+        let path = ModpmLockfile::get_path(&instance);
+        match fs::read_to_string(&path) {
+            Ok(text) => migrate(&text).map_err(|e| format!("{}: {}", path, e)),
+            Err(_) => Ok(vec![]),
+        }
+    }
+
+    /// Runs `work` with everyone else kept out of the instance, so a whole command (an update, a
+    /// sync, ...) happens in one go. Writes inside `work`, and locking it again from there, go
+    /// straight through; other modpm processes, threads and tasks wait until it's done.
+    pub async fn with_instance_locked<F: Future>(instance: &PolyInstance, work: F) -> F::Output {
+        let path = ModpmLockfile::lock_path(instance);
+        if holds_lock(&path) {
+            return work.await;
+        }
+
+        // waiting for the lock mustn't stop whoever has it in this process from finishing
+        let lock_path = path.clone();
+        let file = tokio::task::spawn_blocking(move || ModpmLockfile::lock_file(&lock_path))
+            .await
+            .expect("Couldn't wait for the instance's lock");
+
+        let mut held = HELD_LOCKS.try_with(|held| held.clone()).unwrap_or_default();
+        held.push(path);
+        let output = HELD_LOCKS.scope(held, work).await;
+        drop(file);
+        output
+    }
+
+    /// Locks the instance for one write, so two modpms can't read-modify-write the lockfile over
+    /// each other. Skipped inside `with_instance_locked`, which already has it for the whole command.
+    pub(crate) fn lock(instance: &PolyInstance) -> Option<File> {
+        let path = ModpmLockfile::lock_path(instance);
+        if holds_lock(&path) {
+            return None;
+        }
+
+        Some(ModpmLockfile::lock_file(&path))
+    }
+
+    fn lock_path(instance: &PolyInstance) -> String {
+        format!("{}/mods/.modpm.lock", instance.minecraft_directory())
+    }
+
+    fn lock_file(path: &str) -> File {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).expect("Couldn't create the mods folder");
         }
 
-        let current_lockfile: Vec<LockfileMod> =
-            json5::from_str(&current_lockfile_string[..]).expect("Couldn't deserialize a lockfile");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .expect("Couldn't open the instance's lock file");
+        file.lock()
+            .expect("Couldn't lock the instance, is another modpm stuck?");
 
-        current_lockfile
+        file
     }
+
+    fn save(instance: &PolyInstance, lockfile: &[LockfileMod]) {
+        let text = serde_json::to_string_pretty(&Lockfile {
+            schema_version: LOCKFILE_SCHEMA_VERSION,
+            mods: lockfile.to_vec(),
+        })
+        .expect("Couldn't serialize a lockfile");
+
+        write_atomically(&ModpmLockfile::get_path(instance), &text)
+            .expect("Couldn't save the lockfile");
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it over the top, so a crash halfway
+/// through leaves the old file instead of half of the new one.
+pub(crate) fn write_atomically(path: &str, contents: &str) -> io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = format!("{}.{}.tmp", path, std::process::id());
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp_path, path).inspect_err(|_| {
+        fs::remove_file(&temp_path).ok();
+    })
 }
@@ -0,0 +1,78 @@
+use crate::client::ModrinthClient;
+use crate::data_structs::LockfileMod;
+use crate::modrinth::MpmMod;
+use serde::Serialize;
+
+/// What changed between two lockfiles, by project.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct LockfileDiff {
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    /// Projects in both, with a different version.
+    pub changed: Vec<DiffEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DiffEntry {
+    /// The project's title, or its filename if Modrinth couldn't be asked.
+    pub title: String,
+    pub project_id: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+impl LockfileDiff {
+    pub fn between(old: &[LockfileMod], new: &[LockfileMod]) -> LockfileDiff {
+        let mut diff = LockfileDiff::default();
+
+        let entry = |old: Option<&LockfileMod>, new: Option<&LockfileMod>| {
+            let either = new.or(old).expect("one side has to be there");
+            DiffEntry {
+                title: either.filename.clone(),
+                project_id: either.project_id.clone(),
+                old_version: old.map(|m| m.version_number.clone()),
+                new_version: new.map(|m| m.version_number.clone()),
+            }
+        };
+
+        for new_mod in new {
+            match old.iter().find(|m| m.project_id == new_mod.project_id) {
+                None => diff.added.push(entry(None, Some(new_mod))),
+                Some(old_mod) if old_mod.version_id != new_mod.version_id => {
+                    diff.changed.push(entry(Some(old_mod), Some(new_mod)))
+                }
+                Some(_) => {}
+            }
+        }
+        for old_mod in old {
+            if !new.iter().any(|m| m.project_id == old_mod.project_id) {
+                diff.removed.push(entry(Some(old_mod), None));
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Swaps filenames for project titles where Modrinth knows them, and sorts by title.
+    pub async fn fill_titles(&mut self, client: &ModrinthClient) {
+        let ids: Vec<String> = self.entries().map(|e| e.project_id.clone()).collect();
+        let titles = MpmMod::get_titles(client, &ids).await;
+
+        for list in [&mut self.added, &mut self.removed, &mut self.changed] {
+            for entry in list.iter_mut() {
+                if let Some(title) = titles.get(&entry.project_id) {
+                    entry.title = title.clone();
+                }
+            }
+            list.sort_by_key(|e| e.title.to_lowercase());
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &DiffEntry> {
+        self.added.iter().chain(&self.removed).chain(&self.changed)
+    }
+}
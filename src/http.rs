@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{error::Error, fmt};
+
+use reqwest::{header::HeaderMap, Client, Response, StatusCode};
+
+const MAX_ATTEMPTS: u32 = 4;
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+// never wait longer than this for a rate limit to reset, whatever the server says
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+// set when Modrinth says we've used up our requests, so nothing gets sent until it resets
+static NEXT_ALLOWED: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request never got a response, even after retrying.
+    Request(reqwest::Error),
+    /// The server answered with an error status.
+    Status { url: String, status: u16 },
+    /// Still rate limited after retrying.
+    RateLimited { url: String },
+    /// modpm is in offline mode and this wasn't cached.
+    Offline { url: String },
+}
+
+impl HttpError {
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, HttpError::Status { status: 404, .. })
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Request(error) => write!(f, "request failed: {}", error),
+            HttpError::Status { url, status } => write!(f, "{} returned HTTP {}", url, status),
+            HttpError::RateLimited { url } => write!(f, "still rate limited by {}", url),
+            HttpError::Offline { url } => {
+                write!(f, "{} isn't cached, and modpm is offline", url)
+            }
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+/// Stops every request from going out, for `--offline`. Only cached things work after this.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// One client for the whole run, so connections get reused.
+fn shared_client() -> &'static Client {
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .user_agent(format!(
+                "modpm/{} (https://github.com/Lisenaaaa/modpm)",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+            .expect("Couldn't build an HTTP client")
+    })
+}
+
+/// GETs an API URL, retrying when things go wrong.
+pub async fn get(url: &str) -> Result<Response, HttpError> {
+    send(url, Some(API_TIMEOUT)).await
+}
+
+/// Like `get`, but without an overall timeout, since big files can take a while.
+pub async fn get_download(url: &str) -> Result<Response, HttpError> {
+    send(url, None).await
+}
+
+async fn send(url: &str, timeout: Option<Duration>) -> Result<Response, HttpError> {
+    if is_offline() {
+        return Err(HttpError::Offline {
+            url: url.to_string(),
+        });
+    }
+
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        wait_for_rate_limit().await;
+
+        let mut request = shared_client().get(url);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+
+        let retry_in = match request.send().await {
+            Ok(response) => {
+                note_rate_limit(response.headers());
+                let status = response.status();
+
+                if status.is_success() {
+                    return Ok(response);
+                }
+
+                if status == StatusCode::TOO_MANY_REQUESTS {
+                    if attempt >= MAX_ATTEMPTS {
+                        return Err(HttpError::RateLimited {
+                            url: url.to_string(),
+                        });
+                    }
+                    seconds_header(response.headers(), "retry-after")
+                        .or_else(|| seconds_header(response.headers(), "x-ratelimit-reset"))
+                        .unwrap_or_else(|| backoff(attempt))
+                } else if status.is_server_error() && attempt < MAX_ATTEMPTS {
+                    backoff(attempt)
+                } else {
+                    return Err(HttpError::Status {
+                        url: url.to_string(),
+                        status: status.as_u16(),
+                    });
+                }
+            }
+            Err(error) => {
+                let transient = error.is_timeout() || error.is_connect() || error.is_request();
+                if !transient || attempt >= MAX_ATTEMPTS {
+                    return Err(HttpError::Request(error));
+                }
+                backoff(attempt)
+            }
+        };
+
+        tokio::time::sleep(retry_in.min(MAX_WAIT)).await;
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(attempt - 1))
+}
+
+fn seconds_header(headers: &HeaderMap, name: &str) -> Option<Duration> {
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Remembers to hold off once `X-Ratelimit-Remaining` hits zero.
+fn note_rate_limit(headers: &HeaderMap) {
+    let remaining = headers
+        .get("x-ratelimit-remaining")
+        .and_then(|r| r.to_str().ok())
+        .and_then(|r| r.trim().parse::<u64>().ok());
+
+    if remaining == Some(0) {
+        let reset = seconds_header(headers, "x-ratelimit-reset").unwrap_or(Duration::from_secs(1));
+        *NEXT_ALLOWED.lock().unwrap() = Some(Instant::now() + reset.min(MAX_WAIT));
+    }
+}
+
+async fn wait_for_rate_limit() {
+    let next_allowed = *NEXT_ALLOWED.lock().unwrap();
+
+    if let Some(next_allowed) = next_allowed {
+        let now = Instant::now();
+        if next_allowed > now {
+            tokio::time::sleep(next_allowed - now).await;
+        }
+    }
+}
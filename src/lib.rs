@@ -1,100 +1,156 @@
+pub mod cache;
+pub mod client;
+pub mod config;
 pub mod data_structs;
+pub mod diff;
+pub mod http;
+pub mod loader;
+pub mod manifest;
 pub mod modrinth;
+pub mod mrpack;
 pub mod polymc;
+pub mod resolver;
+pub mod tree;
 
+use cache::Cache;
 use polymc::PolyInstance;
 
 use futures_util::StreamExt;
 use sha2::{Digest, Sha512};
 use std::collections::HashMap;
 use std::fs;
-use std::io::{self, stdin, stdout};
+use std::io::{self, stdin, stdout, IsTerminal};
 use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{error::Error, fs::File, io::Write, process};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-async fn web_get(url: &str) -> Result<reqwest::Response, reqwest::Error> {
-    reqwest::Client::new()
-        .get(url)
-        .header(
-            reqwest::header::USER_AGENT,
-            "modpm/0.1.0 (https://github.com/Lisenaaaa/modpm)",
-        )
-        .send()
-        .await
-}
-
 pub fn format_to_vec_of_strings(data: &serde_json::Value) -> Vec<String> {
     let mut new_data: Vec<String> = vec![];
 
-    if data.is_array() {
-        for items in data.as_array() {
-            for item in items {
-                new_data.push(item.to_string().replace("\"", ""));
-            }
+    if let Some(items) = data.as_array() {
+        for item in items {
+            new_data.push(item.to_string().replace('"', ""));
         }
     }
 
     new_data
 }
 
+/// Downloads a file into `path`, or takes it from the cache if a file with that sha512 is there.
 pub async fn download_file(
     url: String,
     path: String,
     filename: String,
+    sha512: &str,
 ) -> Result<(), Box<dyn Error>> {
-    let res = web_get(&url[..]).await.expect("failed to get the url");
-
-    let total_size = res
-        .content_length()
-        .expect("failed to get the content length");
-
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::with_template("[{wide_bar:.green/magenta}] {bytes}/{total_bytes}")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
-
-    let mut file =
-        File::create(format!("{}/{}", path, filename)).expect("failed to create the file");
-    let mut downloaded: u64 = 0;
-    let mut stream = res.bytes_stream();
+    let destination = format!("{}/{}", path, filename);
+    if Cache::link_file(sha512, &destination) {
+        eprintln!(
+            "{}",
+            ansi_term::Color::RGB(128, 128, 128).paint("Found it in the cache")
+        );
+        return Ok(());
+    }
 
-    while let Some(item) = stream.next().await {
-        let chunk = item.expect("error while downloading file");
+    let res = http::get_download(&url[..]).await?;
+
+    let pb = match res.content_length() {
+        Some(total_size) => ProgressBar::new(total_size).with_style(
+            ProgressStyle::with_template("[{wide_bar:.green/magenta}] {bytes}/{total_bytes}")
+                .unwrap()
+                .progress_chars("=>-"),
+        ),
+        // chunked responses don't say how big they are
+        None => ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template("{spinner:.green} {bytes}").unwrap()),
+    };
 
-        file.write_all(&chunk).expect("error while writing to file");
+    let mut file = File::create(&destination)?;
+    let mut stream = res.bytes_stream();
 
-        let new = std::cmp::min(downloaded + (chunk.len() as u64), total_size);
-        downloaded = new;
-        pb.set_position(new);
+    while let Some(item) = stream.next().await {
+        let chunk = item?;
+        file.write_all(&chunk)?;
+        pb.inc(chunk.len() as u64);
     }
 
     pb.finish_with_message("Downloaded");
 
+    Cache::store_file(&destination, sha512);
+
     Ok(())
 }
 
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// With `--json`, stdout is only for the JSON, so anything meant for people goes to stderr.
+pub fn set_json_output(json: bool) {
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// `println!` for things meant for people, which moves out of the way of `--json`.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::json_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Makes prompts that have a sensible default answer themselves, for `--yes`.
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
 pub fn ask_user(query: &str) -> String {
+    if !stdin().is_terminal() {
+        crash(format!(
+            "modpm needed an answer to \"{}\", but isn't running in a terminal. Pass it as a flag instead (see --help).",
+            query.trim()
+        ));
+    }
+
     let mut response = String::new();
-    print!("{}", query);
-    stdout().flush().unwrap();
+    if json_output() {
+        eprint!("{}", query);
+    } else {
+        print!("{}", query);
+        stdout().flush().unwrap();
+    }
 
     stdin().read_line(&mut response).unwrap();
 
     response.trim().to_string()
 }
 
+/// Like `ask_user`, but goes with `default` straight away when `--yes` was passed.
+pub fn ask_user_with_default(query: &str, default: &str) -> String {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        say!("{}{}", query, default);
+        return default.to_string();
+    }
+
+    ask_user(query)
+}
+
 pub fn parse_cfg_file(filepath: String) -> HashMap<String, String> {
     let file = fs::read_to_string(filepath).unwrap();
-    let file_split: Vec<&str> = file.split("\n").filter(|c| *c != "").collect();
+    let file_split: Vec<&str> = file.split('\n').filter(|c| !c.is_empty()).collect();
 
     let mut map: HashMap<String, String> = HashMap::new();
 
     for data in file_split {
-        let split_data: Vec<&str> = data.split("=").collect();
+        let split_data: Vec<&str> = data.split('=').collect();
 
         map.insert(split_data[0].to_string(), split_data[1].to_string());
     }
@@ -102,15 +158,15 @@ pub fn parse_cfg_file(filepath: String) -> HashMap<String, String> {
     map
 }
 
-pub fn crash(reason: String) -> String {
+pub fn crash(reason: String) -> ! {
     eprintln!("{}", reason);
     process::exit(1);
 }
 
 pub fn hash_file(path: &str) -> Result<String, Box<dyn Error>> {
-    let mut file = File::open(path).unwrap();
+    let mut file = File::open(path)?;
     let mut hasher = Sha512::new();
-    io::copy(&mut file, &mut hasher).unwrap();
+    io::copy(&mut file, &mut hasher)?;
     let result = hasher.finalize();
 
     let hash = hex::encode(result);
@@ -0,0 +1,306 @@
+use crate::config::Config;
+use crate::http;
+use crate::polymc::{PolyInstance, PolyMC};
+use std::cmp::Ordering;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+
+pub const DEFAULT_META_BASE: &str = "https://meta.polymc.org/v1";
+
+/// NeoForge's only release that was still a drop-in fork of Forge. From 1.20.2 on it changed its
+/// packages and mod format, so Forge and NeoForge mods stop working on each other's loader.
+pub const NEOFORGE_FORGE_COMPATIBLE_VERSION: &str = "1.20.1";
+
+/// The loaders whose mods will run on `loader` for a game version, native loader first.
+///
+/// Quilt can load Fabric mods, and Forge and NeoForge mods work on each other's loader on
+/// `NEOFORGE_FORGE_COMPATIBLE_VERSION`.
+pub fn compatible_loaders(loader: &str, game_version: &str) -> Vec<String> {
+    let mut loaders = vec![loader.to_string()];
+
+    let forks_compatible = game_version == NEOFORGE_FORGE_COMPATIBLE_VERSION;
+    match loader {
+        "quilt" => loaders.push("fabric".to_string()),
+        "neoforge" if forks_compatible => loaders.push("forge".to_string()),
+        "forge" if forks_compatible => loaders.push("neoforge".to_string()),
+        _ => {}
+    }
+
+    loaders
+}
+
+/// The loaders a version of a project needs to list to work in an instance, best first.
+///
+/// Only mods care about the instance's modloader; shaders are picked by the shader loader the
+/// instance would use, and resource packs and data packs just need the game itself.
+pub fn accepted_loaders(project_type: &str, instance: &PolyInstance) -> Vec<String> {
+    match project_type {
+        "resourcepack" => vec!["minecraft".to_string()],
+        "datapack" => vec!["datapack".to_string()],
+        "shader" => match &instance.modloader[..] {
+            "fabric" | "quilt" | "neoforge" => vec!["iris".to_string(), "vanilla".to_string()],
+            _ => vec!["optifine".to_string(), "vanilla".to_string()],
+        },
+        _ => compatible_loaders(&instance.modloader, &instance.game_version),
+    }
+}
+
+/// How good a fit a version built for `version_loaders` is, lower being better.
+/// `None` means it won't run at all.
+pub fn loader_rank(version_loaders: &[String], accepted: &[String]) -> Option<usize> {
+    accepted.iter().position(|l| version_loaders.contains(l))
+}
+
+/// Uses `$MODPM_META_BASE`, then the config file, then PolyMC's own metadata server.
+pub fn meta_base() -> String {
+    env::var("MODPM_META_BASE").unwrap_or_else(|_| {
+        Config::load()
+            .meta_base
+            .unwrap_or_else(|| DEFAULT_META_BASE.to_string())
+    })
+}
+
+/// The newest version of a modloader that works on a game version, going by the launcher's
+/// metadata. Recommended versions win over newer ones that aren't.
+pub async fn latest_loader_version(
+    meta_base: &str,
+    loader: &str,
+    game_version: &str,
+) -> Result<String, String> {
+    let uid = PolyMC::get_loader_uid(loader)
+        .ok_or_else(|| format!("I don't know what {} is.", loader))?;
+    let url = format!("{}/{}/index.json", meta_base, uid);
+
+    let index: serde_json::Value = http::get(&url)
+        .await
+        .map_err(|e| format!("Couldn't get the versions of {}: {}", loader, e))?
+        .json()
+        .await
+        .map_err(|e| format!("Couldn't read the versions of {}: {}", loader, e))?;
+
+    // forge builds say which game version they're for, fabric and quilt work on all of them
+    let fits = |version: &&serde_json::Value| {
+        version["requires"]
+            .as_array()
+            .map(|requires| {
+                requires.iter().all(|r| {
+                    r["uid"] != "net.minecraft"
+                        || r["equals"].is_null()
+                        || r["equals"] == game_version
+                })
+            })
+            .unwrap_or(true)
+    };
+    // the index is newest first
+    let versions: Vec<&serde_json::Value> = index["versions"]
+        .as_array()
+        .map(|versions| versions.iter().filter(fits).collect())
+        .unwrap_or_default();
+
+    versions
+        .iter()
+        .find(|v| v["recommended"] == true)
+        .or_else(|| versions.first())
+        .and_then(|v| v["version"].as_str())
+        .map(|v| v.to_string())
+        .ok_or_else(|| format!("There's no version of {} for {}.", loader, game_version))
+}
+
+/// Whether a version range (the first argument) allows a version (the second).
+type RangeCheck = fn(&str, &str) -> bool;
+
+/// Checks a downloaded mod's own metadata for the version of `loader` it needs. Modrinth doesn't
+/// say, so the jar is the only place to find out. Anything modpm can't read is let through.
+pub fn check_loader_version(jar: &str, loader: &str, loader_version: &str) -> Result<(), String> {
+    let mut archive = match File::open(jar)
+        .ok()
+        .and_then(|f| zip::ZipArchive::new(f).ok())
+    {
+        Some(archive) => archive,
+        None => return Ok(()),
+    };
+    let mut read = |name: &str| -> Option<String> {
+        let mut text = String::new();
+        archive.by_name(name).ok()?.read_to_string(&mut text).ok()?;
+        Some(text)
+    };
+
+    // each is a list of ranges, and any one of them will do
+    let (requirements, allows): (Vec<String>, RangeCheck) = match loader {
+        "fabric" => (
+            read("fabric.mod.json")
+                .and_then(|text| json5::from_str::<serde_json::Value>(&text).ok())
+                .map(|json| string_or_list(&json["depends"]["fabricloader"]))
+                .unwrap_or_default(),
+            fabric_range_allows,
+        ),
+        "quilt" => (
+            read("quilt.mod.json")
+                .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+                .map(|json| {
+                    json["quilt_loader"]["depends"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter(|d| d["id"] == "quilt_loader")
+                        .flat_map(|d| string_or_list(&d["versions"]))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            fabric_range_allows,
+        ),
+        "forge" | "neoforge" => (
+            read("META-INF/neoforge.mods.toml")
+                .or_else(|| read("META-INF/mods.toml"))
+                .and_then(|text| toml::from_str::<toml::Value>(&text).ok())
+                .map(|mods_toml| {
+                    mods_toml
+                        .get("dependencies")
+                        .and_then(|d| d.as_table())
+                        .into_iter()
+                        .flat_map(|d| d.values())
+                        .filter_map(|d| d.as_array())
+                        .flatten()
+                        .filter(|d| d.get("modId").and_then(|m| m.as_str()) == Some(loader))
+                        .filter_map(|d| d.get("versionRange").and_then(|r| r.as_str()))
+                        .map(|r| r.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            maven_range_allows,
+        ),
+        _ => (vec![], fabric_range_allows),
+    };
+
+    if requirements.is_empty() || requirements.iter().any(|r| allows(r, loader_version)) {
+        Ok(())
+    } else {
+        Err(requirements.join(" or "))
+    }
+}
+
+fn string_or_list(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(s) => vec![s.clone()],
+        serde_json::Value::Array(list) => list
+            .iter()
+            .filter_map(|v| v.as_str().map(|s| s.to_string()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Fabric and Quilt style ranges, like ">=0.15.0", "^0.14.x" or "*". Spaces between them mean
+/// all of them have to match, `^` means the same major version and `~` the same minor one.
+pub fn fabric_range_allows(requirement: &str, version: &str) -> bool {
+    // "0.15.0-beta.1" would never match ">=0.14" as a prerelease, but it's newer, so it does
+    let version = match release_numbers(version) {
+        Some((version, false)) => version,
+        _ => return true,
+    };
+
+    requirement.split_whitespace().all(|predicate| {
+        let operator = [">=", "<=", ">", "<", "=", "^", "~"]
+            .into_iter()
+            .find(|o| predicate.starts_with(o))
+            .unwrap_or("");
+        let (wanted, wildcard) = match release_numbers(&predicate[operator.len()..]) {
+            Some(wanted) => wanted,
+            // a bare "*", or something this doesn't understand
+            None => return true,
+        };
+
+        let same_prefix = |len: usize| version.iter().take(len).eq(wanted.iter().take(len));
+        let order = compare_numbers(&version, &wanted);
+        match operator {
+            ">=" => order != Ordering::Less,
+            "<=" => order != Ordering::Greater,
+            ">" => order == Ordering::Greater,
+            "<" => order == Ordering::Less,
+            "^" => order != Ordering::Less && same_prefix(1),
+            "~" => order != Ordering::Less && same_prefix(2),
+            // "0.14.x" is anything starting with 0.14
+            _ if wildcard => same_prefix(wanted.len()),
+            _ => order == Ordering::Equal,
+        }
+    })
+}
+
+/// The numbers in a version, without any prerelease or build bits, and whether it ended in an
+/// "x" or "*" wildcard. `None` if it isn't numbers.
+fn release_numbers(version: &str) -> Option<(Vec<u64>, bool)> {
+    let release = version.split(['-', '+']).next().unwrap_or(version);
+    let mut numbers = vec![];
+    for part in release.split('.') {
+        match part {
+            "x" | "X" | "*" => return (!numbers.is_empty()).then_some((numbers, true)),
+            part => numbers.push(part.parse().ok()?),
+        }
+    }
+    Some((numbers, false))
+}
+
+fn compare_numbers(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    let (mut a, mut b) = (a.to_vec(), b.to_vec());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a.cmp(&b)
+}
+
+/// Forge style (maven) ranges, like "[47,)" or "[20.4.80,20.5)". A bare version means anything.
+fn maven_range_allows(range: &str, version: &str) -> bool {
+    let range = range.trim();
+    if !range.starts_with(['[', '(']) {
+        return true;
+    }
+
+    // a union like "[1,2),[3,4)" is split on the commas between sets
+    let mut sets = vec![];
+    let mut current = String::new();
+    for c in range.chars() {
+        if current.is_empty() && c == ',' {
+            continue;
+        }
+        current.push(c);
+        if c == ']' || c == ')' {
+            sets.push(std::mem::take(&mut current));
+        }
+    }
+
+    sets.iter().any(|set| {
+        let lower_inclusive = set.starts_with('[');
+        let upper_inclusive = set.ends_with(']');
+        let inner = &set[1..set.len() - 1];
+
+        match inner.split_once(',') {
+            None => compare_versions(version, inner) == Ordering::Equal,
+            Some((lower, upper)) => {
+                let lower_ok = lower.trim().is_empty()
+                    || match compare_versions(version, lower.trim()) {
+                        Ordering::Greater => true,
+                        Ordering::Equal => lower_inclusive,
+                        Ordering::Less => false,
+                    };
+                let upper_ok = upper.trim().is_empty()
+                    || match compare_versions(version, upper.trim()) {
+                        Ordering::Less => true,
+                        Ordering::Equal => upper_inclusive,
+                        Ordering::Greater => false,
+                    };
+                lower_ok && upper_ok
+            }
+        }
+    })
+}
+
+/// Compares dotted version numbers by their numbers, so "47.10" > "47.9" and "47" == "47.0".
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |v: &str| -> Vec<u64> {
+        v.split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    compare_numbers(&numbers(a), &numbers(b))
+}
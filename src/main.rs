@@ -1,7 +1,24 @@
 use std::collections::HashMap;
 
 use clap::{arg, Command};
-use modpm::{ask_user, modrinth::MpmMod, polymc::PolyMC};
+use modpm::{
+    ask_user,
+    cache::Cache,
+    client::ModrinthClient,
+    crash,
+    data_structs::{LockfileMod, ModpmLockfile},
+    diff::{DiffEntry, LockfileDiff},
+    http, loader,
+    manifest::Manifest,
+    modrinth::DownloadOptions,
+    modrinth::MpmMod,
+    mrpack::Mrpack,
+    polymc::{MigrationMod, PolyInstance, PolyMC},
+    resolver::OptionalDependencies,
+    say, set_assume_yes, set_json_output,
+    tree::DependencyTree,
+};
+use serde::Serialize;
 
 fn cli() -> Command<'static> {
     Command::new("modpm")
@@ -9,11 +26,28 @@ fn cli() -> Command<'static> {
         .subcommand_required(true)
         .arg_required_else_help(true)
         .allow_external_subcommands(false)
+        .arg(
+            arg!(-y --yes "Go with the default answer instead of asking, where there is one")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            arg!(--offline "Don't touch the network, only use what's already cached")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            arg!(--json "Print results as JSON instead of text")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        )
         .subcommand(
             Command::new("query")
                 .about("Queries a mod")
                 .arg(arg!(<MOD> "The mod to query."))
                 .arg(arg!(-v --versions "Show recent versions.").action(clap::ArgAction::SetTrue))
+                .arg(arg!(--game <VERSION> "Only look at versions for this Minecraft version").required(false))
+                .arg(arg!(--loader <LOADER> "Only look at versions for this modloader").required(false))
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -24,18 +58,249 @@ fn cli() -> Command<'static> {
                     arg!(-s --specific_version "Download a version of your choice")
                         .action(clap::ArgAction::SetTrue),
                 )
+                .arg(
+                    arg!(--"with-optional" "Install all optional dependencies without asking")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--"no-optional" "Don't install any optional dependencies")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("with-optional"),
+                )
+                .arg(arg!(--world <WORLD> "The world to put a data pack in").required(false))
+                .arg(instance_arg())
+                .arg(
+                    arg!(--version <VERSION> "The version to download: an id, a version number, or \"latest\"")
+                        .required(false),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("tree")
+                .about("Shows the dependency tree of a mod, or of every mod in an instance")
+                .arg(arg!([MOD] "The mod to show the tree of. Leave empty for the whole instance."))
+                .arg(
+                    arg!(-r --reverse "Show what depends on MOD instead")
+                        .action(clap::ArgAction::SetTrue)
+                        .requires("MOD"),
+                )
+                .arg(instance_arg()),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Searches Modrinth for mods")
+                .arg(arg!(<QUERY> "What to search for."))
+                .arg(
+                    arg!(-i --instance <INSTANCE> "Only show mods that work with this instance")
+                        .required(false),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(Command::new("instances").about("Lists your PolyMC instances"))
+        .subcommand(
+            Command::new("instance")
+                .about("Manages PolyMC instances")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("Makes a new instance, optionally with mods from a modpm.toml or a .mrpack")
+                        .arg(arg!(--name <NAME> "What to call it").required(false))
+                        .arg(arg!(--game <VERSION> "The Minecraft version").required(false))
+                        .arg(
+                            arg!(--loader <LOADER> "fabric, quilt, forge, neoforge, or vanilla")
+                                .required(false),
+                        )
+                        .arg(
+                            arg!(--"loader-version" <VERSION> "The modloader's version, instead of the newest one")
+                                .required(false),
+                        )
+                        .arg(arg!(--manifest <FILE> "A modpm.toml to install the mods of").required(false))
+                        .arg(
+                            arg!(--mrpack <FILE> "A Modrinth modpack to install, which also picks the game and loader if you don't")
+                                .required(false)
+                                .conflicts_with("manifest"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the mods modpm installed in an instance")
+                .arg(instance_arg()),
+        )
+        .subcommand(
+            Command::new("outdated")
+                .about("Lists the mods in an instance that have updates")
+                .arg(instance_arg()),
+        )
+        .subcommand(
+            Command::new("update")
+                .about("Works out an instance's mods again from its modpm.toml, and installs any changes")
+                .arg(instance_arg()),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Makes an instance's mods match its lockfile exactly")
+                .arg(instance_arg())
+                .arg(
+                    arg!(--"remove-untracked" "Also delete files that modpm didn't install")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks an instance's mods are the ones in its lockfile")
+                .arg(instance_arg())
+                .arg(
+                    arg!(--repair "Download missing and changed files again")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("enable")
+                .about("Turns a disabled mod back on")
+                .arg(arg!(<MOD> "The mod, by filename or project id"))
+                .arg(instance_arg())
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("disable")
+                .about("Turns a mod off without uninstalling it, like PolyMC does")
+                .arg(arg!(<MOD> "The mod, by filename or project id"))
+                .arg(instance_arg())
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("copy")
+                .about("Installs the mods from one instance into another, with versions that fit it")
+                .arg(arg!(<FROM> "The instance to copy the mods of"))
+                .arg(arg!(<TO> "The instance to put them in"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Shows which mods were added, removed, or changed version between two lockfiles")
+                .arg(arg!(<OLD> "An instance, a lockfile, or - to read a lockfile from stdin"))
+                .arg(arg!(<NEW> "An instance, a lockfile, or - to read a lockfile from stdin"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("migrate-check")
+                .about("Checks whether an instance's mods are ready for another Minecraft version")
+                .arg(arg!(<INSTANCE> "The instance to check"))
+                .arg(arg!(<VERSION> "The Minecraft version to move to"))
+                .arg(
+                    arg!(--apply "Change the instance to that version and update its mods, if nothing's missing")
+                        .action(clap::ArgAction::SetTrue),
+                )
                 .arg_required_else_help(true),
         )
-    // .subcommand(
-    //     Command::new("update").about("Update all of your mods from a specific instance"),
-    // )
+        .subcommand(
+            Command::new("cache")
+                .about("Looks after modpm's download and API cache")
+                .subcommand_required(true)
+                .subcommand(Command::new("info").about("Shows where the cache is and how big it is"))
+                .subcommand(
+                    Command::new("clean")
+                        .about("Empties the cache")
+                        .arg(
+                            arg!(--files "Only remove cached mod files")
+                                .action(clap::ArgAction::SetTrue),
+                        )
+                        .arg(
+                            arg!(--api "Only remove cached API responses")
+                                .action(clap::ArgAction::SetTrue)
+                                .conflicts_with("files"),
+                        ),
+                ),
+        )
     // .subcommand(Command::new("polymc").about("testing lmao"))
     // .subcommand(Command::new("test").about("even more testing"))
 }
 
+fn instance_arg() -> clap::Arg<'static> {
+    arg!(-i --instance <INSTANCE> "The instance to use, by name, folder name, or the start of either").required(false)
+}
+
+fn print_instance(instance: &PolyInstance) {
+    say!(
+        "{} {} - {} {}",
+        ansi_term::Color::Blue.paint(&instance.name),
+        ansi_term::Color::RGB(128, 128, 128).paint(format!("[{}]", instance.folder_name)),
+        ansi_term::Color::Purple.paint(&instance.modloader),
+        ansi_term::Color::Green.paint(&instance.game_version)
+    );
+}
+
+fn pick_instance(query: &str, instance_arg: Option<&String>, json: bool) -> PolyInstance {
+    if let Some(wanted) = instance_arg {
+        return PolyMC::find_instance(wanted).unwrap_or_else(|e| crash(e));
+    }
+    if json {
+        crash("Pick an instance with --instance when using --json.".to_string());
+    }
+
+    let instances = PolyMC::get_instances().expect("Couldn't get PolyMC instances.");
+    for instance in &instances {
+        print_instance(instance);
+    }
+
+    let instance_name = ask_user(query);
+
+    PolyMC::find_instance(&instance_name).unwrap_or_else(|e| crash(e))
+}
+
+/// A lockfile from an instance, a file, or stdin, e.g. `git show HEAD~:mods/.modpm_lockfile.json | modpm diff - .`
+fn read_diff_side(arg: &str) -> Result<Vec<LockfileMod>, String> {
+    if arg == "-" {
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut text)
+            .map_err(|e| format!("Couldn't read a lockfile from stdin: {}", e))?;
+        return ModpmLockfile::parse_lockfile(&text).map_err(|e| format!("stdin: {}", e));
+    }
+
+    if std::path::Path::new(arg).is_file() {
+        return ModpmLockfile::read_lockfile_file(arg);
+    }
+
+    ModpmLockfile::get_lockfile(PolyMC::find_instance(arg)?)
+}
+
+/// 1234567 -> "1,234,567"
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (num, digit) in digits.chars().enumerate() {
+        if num > 0 && (digits.len() - num).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("Couldn't turn the output into JSON")
+    );
+}
+
 #[tokio::main]
 async fn main() {
     let matches = cli().get_matches();
+    set_assume_yes(*matches.get_one::<bool>("yes").expect("how"));
+    let json = *matches.get_one::<bool>("json").expect("how");
+    set_json_output(json);
+    http::set_offline(*matches.get_one::<bool>("offline").expect("how"));
+    let client = ModrinthClient::from_config();
 
     match matches.subcommand() {
         Some(("query", sub_matches)) => {
@@ -43,25 +308,59 @@ async fn main() {
 
             let versions = sub_matches.get_one::<bool>("versions").expect("how");
 
-            let mod_data = match MpmMod::new(mmod).await {
+            let mut mod_data = match MpmMod::new(&client, mmod).await {
                 Ok(data) => data,
-                Err(_) => MpmMod::new_from_hash(mmod).await,
+                Err(_) => MpmMod::new_from_hash(&client, mmod).await,
             };
+            // newest first, and only the ones that fit --game and --loader
+            mod_data.versions = mod_data.filter_versions(
+                sub_matches.get_one::<String>("game").map(|g| &g[..]),
+                sub_matches.get_one::<String>("loader").map(|l| &l[..]),
+            );
 
-            println!(
+            if json {
+                print_json(&mod_data);
+                return;
+            }
+
+            say!(
                 "I found {}{}, which is licensed under {}, and located at {}",
                 ansi_term::Color::Green.paint(&mod_data.title),
                 ansi_term::Color::RGB(128, 128, 128).paint(format!(" ({})", mod_data.id)),
                 ansi_term::Color::Green.paint(&mod_data.license.name),
                 ansi_term::Color::RGB(255, 165, 0).paint(&mod_data.source_url)
             );
-            println!("{}", mod_data.description);
+            say!("{}", mod_data.description);
+            say!(
+                "{} downloads, {} followers",
+                ansi_term::Color::Green.paint(format_count(mod_data.downloads)),
+                ansi_term::Color::Green.paint(format_count(mod_data.followers))
+            );
+            say!(
+                "Type: {}, client: {}, server: {}",
+                ansi_term::Color::Purple.paint(&mod_data.project_type),
+                mod_data.client_side,
+                mod_data.server_side
+            );
+            if !mod_data.categories.is_empty() {
+                say!(
+                    "Categories: {}",
+                    ansi_term::Color::Blue.paint(mod_data.categories.join(", "))
+                );
+            }
+            for donation in &mod_data.donation_urls {
+                say!(
+                    "Donate on {}: {}",
+                    donation.platform,
+                    ansi_term::Color::RGB(255, 165, 0).paint(&donation.url)
+                );
+            }
 
             let mut members: HashMap<String, Vec<String>> = HashMap::new();
             members.insert("Owner".to_string(), vec![]);
 
             for member in mod_data.members {
-                let _entry = match members.entry(member.role) {
+                match members.entry(member.role) {
                     std::collections::hash_map::Entry::Vacant(role) => {
                         let new_value = vec![member.user.name.unwrap_or(member.user.username)];
                         role.insert(new_value);
@@ -73,20 +372,50 @@ async fn main() {
                 };
             }
 
-            println!(
+            say!(
                 "Owner: {}",
                 ansi_term::Color::Purple.paint(members.remove("Owner").unwrap().join(", "))
             );
 
             for (role, people) in members {
-                println!("{}: {}", role, people.join(", "));
+                say!("{}: {}", role, people.join(", "));
             }
-            if versions == &true {
-                let mut versions_vec = mod_data.versions.clone();
-                versions_vec.reverse();
+            match mod_data.versions.first() {
+                Some(latest) if !latest.dependencies.is_empty() => {
+                    let mut dependencies = vec![];
+                    for dep in &latest.dependencies {
+                        dependencies.push((dep.resolve_project(&client).await, dep));
+                    }
+                    let ids: Vec<String> = dependencies.iter().map(|(id, _)| id.clone()).collect();
+                    let titles = MpmMod::get_titles(&client, &ids).await;
 
-                for v in versions_vec {
-                    println!(
+                    say!(
+                        "Dependencies of {}:",
+                        ansi_term::Color::RGB(128, 128, 128).paint(&latest.version_number)
+                    );
+                    for (project_id, dep) in dependencies {
+                        say!(
+                            "  {} {}",
+                            ansi_term::Color::Green
+                                .paint(titles.get(&project_id).unwrap_or(&project_id)),
+                            match &dep.dependency_type[..] {
+                                "required" =>
+                                    ansi_term::Color::RGB(128, 128, 128).paint("[required]"),
+                                "optional" => ansi_term::Color::Yellow.paint("[optional]"),
+                                "incompatible" => ansi_term::Color::Red.paint("[incompatible]"),
+                                _ => ansi_term::Color::Blue
+                                    .paint(format!("[{}]", dep.dependency_type)),
+                            }
+                        );
+                    }
+                }
+                Some(_) => {}
+                None => say!("{}", ansi_term::Color::Red.paint("No versions match that.")),
+            }
+
+            if versions == &true {
+                for v in mod_data.versions {
+                    say!(
                         "{} {}\n\t├ Game versions: {}\n\t└ Modloaders: {}",
                         ansi_term::Color::Green.paint(v.name),
                         ansi_term::Color::RGB(128, 128, 128)
@@ -101,14 +430,21 @@ async fn main() {
             let specific_version = sub_matches
                 .get_one::<bool>("specific_version")
                 .expect("how");
+            let optional = if *sub_matches.get_one::<bool>("with-optional").expect("how") {
+                OptionalDependencies::Install
+            } else if *sub_matches.get_one::<bool>("no-optional").expect("how") {
+                OptionalDependencies::Skip
+            } else {
+                OptionalDependencies::Ask
+            };
 
             let mod_arg = sub_matches.get_one::<String>("MOD").expect("required");
-            let mod_data = match MpmMod::new(mod_arg).await {
+            let mut mod_data = match MpmMod::new(&client, mod_arg).await {
                 Ok(data) => data,
-                Err(_) => MpmMod::new_from_hash(mod_arg).await,
+                Err(_) => MpmMod::new_from_hash(&client, mod_arg).await,
             };
 
-            println!(
+            say!(
                 "I found {}{} by {} - {}\n",
                 ansi_term::Color::Green.paint(&mod_data.title),
                 ansi_term::Color::RGB(128, 128, 128).paint(format!(" ({})", mod_data.id)),
@@ -122,49 +458,563 @@ async fn main() {
                 mod_data.description
             );
 
-            let instances = PolyMC::get_instances().expect("Couldn't get PolyMC instances.");
-            for instance in &instances {
-                println!(
-                    "{}: {} - {} {}",
-                    instance.id,
-                    ansi_term::Color::Blue.paint(&instance.name),
-                    ansi_term::Color::Purple.paint(&instance.modloader),
-                    ansi_term::Color::Green.paint(&instance.game_version)
-                );
+            let instance = pick_instance(
+                "What instance do you want to download this mod to? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+
+            let world = sub_matches.get_one::<String>("world").cloned();
+            if world.is_some() && mod_data.project_type == "mod" {
+                // projects that ship both a mod and a data pack get installed as the data pack
+                if !mod_data
+                    .versions
+                    .iter()
+                    .any(|v| v.loaders.contains(&"datapack".to_string()))
+                {
+                    crash(format!(
+                        "{} doesn't have a data pack version.",
+                        ansi_term::Color::Green.paint(&mod_data.title)
+                    ));
+                }
+                mod_data.project_type = "datapack".to_string();
             }
 
-            let instance_id = ask_user("What instance do you want to download this mod to? ");
+            mod_data
+                .download(
+                    &client,
+                    instance,
+                    DownloadOptions {
+                        choose_version: *specific_version,
+                        version: sub_matches.get_one::<String>("version").cloned(),
+                        optional,
+                        world,
+                    },
+                )
+                .await;
+        }
+        Some(("search", sub_matches)) => {
+            let query = sub_matches.get_one::<String>("QUERY").expect("required");
+            let instance = sub_matches
+                .get_one::<String>("instance")
+                .map(|wanted| PolyMC::find_instance(wanted).unwrap_or_else(|e| crash(e)));
+
+            let hits = MpmMod::search(&client, query, instance.as_ref()).await;
 
-            let instance = instances
-                .into_iter()
-                .find(|i| i.id.to_string() == instance_id)
-                .expect("Couldn't find that instance.");
+            if json {
+                print_json(&hits);
+                return;
+            }
 
-            mod_data.download(instance, *specific_version).await;
+            for hit in hits {
+                say!(
+                    "{}{} by {} - {}",
+                    ansi_term::Color::Green.paint(&hit.title),
+                    ansi_term::Color::RGB(128, 128, 128).paint(format!(" ({})", hit.slug)),
+                    ansi_term::Color::Purple.paint(&hit.author),
+                    hit.description
+                );
+            }
         }
-        Some(("update", _)) => {
+        Some(("instances", _)) => {
             let instances = PolyMC::get_instances().expect("Couldn't get PolyMC instances.");
+
+            if json {
+                print_json(&instances);
+                return;
+            }
+
             for instance in &instances {
-                println!(
-                    "{}: {} - {} {}",
-                    instance.id,
-                    ansi_term::Color::Blue.paint(&instance.name),
-                    ansi_term::Color::Purple.paint(&instance.modloader),
-                    ansi_term::Color::Green.paint(&instance.game_version)
+                print_instance(instance);
+            }
+        }
+        Some(("list", sub_matches)) => {
+            let instance = pick_instance(
+                "What instance do you want to list the mods of? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+            let lockfile = ModpmLockfile::get_lockfile(instance).unwrap_or_else(|e| crash(e));
+
+            if json {
+                print_json(&lockfile);
+                return;
+            }
+
+            for installed in lockfile {
+                say!(
+                    "{} {}{}{}",
+                    ansi_term::Color::Green.paint(&installed.filename),
+                    ansi_term::Color::RGB(128, 128, 128)
+                        .paint(format!("({})", installed.version_number)),
+                    if installed.dependency_of().is_some() {
+                        ansi_term::Color::Yellow.paint(" [dependency]").to_string()
+                    } else {
+                        String::new()
+                    },
+                    if installed.disabled {
+                        ansi_term::Color::Red.paint(" [disabled]").to_string()
+                    } else {
+                        String::new()
+                    }
                 );
             }
+        }
+        Some(("outdated", sub_matches)) => {
+            let instance = pick_instance(
+                "What instance do you want to check? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+            let outdated = instance.outdated(&client).await;
 
-            let instance_id = ask_user("What instance do you want to download this mod to? ");
+            if json {
+                print_json(&outdated);
+                return;
+            }
 
-            let instance = instances
-                .into_iter()
-                .find(|i| i.id.to_string() == instance_id)
-                .expect("Couldn't find that instance.");
+            for outdated_mod in outdated {
+                say!(
+                    "{} {} -> {}",
+                    ansi_term::Color::Green.paint(&outdated_mod.title),
+                    ansi_term::Color::RGB(128, 128, 128)
+                        .paint(&outdated_mod.installed.version_number),
+                    ansi_term::Color::Purple.paint(&outdated_mod.latest.version_number)
+                );
+            }
+        }
+        Some(("update", sub_matches)) => {
+            let instance = pick_instance(
+                "What instance do you want to update? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
 
             instance
-                .update()
+                .update(&client)
+                .await
+                .unwrap_or_else(|e| crash(e.to_string()));
+        }
+        Some(("sync", sub_matches)) => {
+            let instance = pick_instance(
+                "What instance do you want to sync? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+            let remove_untracked = *sub_matches
+                .get_one::<bool>("remove-untracked")
+                .expect("how");
+
+            let report = instance.sync(remove_untracked).await;
+
+            if json {
+                print_json(&report);
+                return;
+            }
+
+            for removed in &report.removed {
+                say!("{} {}", ansi_term::Color::Red.paint("Removed"), removed);
+            }
+            say!(
+                "{} {} downloaded, {} removed, {} already up to date",
+                ansi_term::Color::Green.paint("Synced!"),
+                report.downloaded.len(),
+                report.removed.len(),
+                report.unchanged.len()
+            );
+        }
+        Some(("verify", sub_matches)) => {
+            let instance = pick_instance(
+                "What instance do you want to verify? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+            let repair = *sub_matches.get_one::<bool>("repair").expect("how");
+
+            let report = instance.verify(repair).await;
+
+            if json {
+                print_json(&report);
+            } else {
+                for missing in &report.missing {
+                    say!("{} {}", ansi_term::Color::Red.paint("Missing"), missing);
+                }
+                for modified in &report.modified {
+                    say!("{} {}", ansi_term::Color::Red.paint("Modified"), modified);
+                }
+                for untracked in &report.untracked {
+                    say!(
+                        "{} {}",
+                        ansi_term::Color::Yellow.paint("Untracked"),
+                        untracked
+                    );
+                }
+                for repaired in &report.repaired {
+                    say!("{} {}", ansi_term::Color::Green.paint("Repaired"), repaired);
+                }
+                if report.missing.is_empty() && report.modified.is_empty() {
+                    say!(
+                        "{} {} files match the lockfile",
+                        ansi_term::Color::Green.paint("All good!"),
+                        report.ok.len()
+                    );
+                }
+            }
+
+            // broken files that weren't fixed should fail scripts and CI
+            if report.repaired.len() < report.missing.len() + report.modified.len() {
+                std::process::exit(1);
+            }
+        }
+        Some((command @ ("enable" | "disable"), sub_matches)) => {
+            let enabled = command == "enable";
+            let instance = pick_instance(
+                &format!("What instance do you want to {} a mod in? ", command),
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+            let mod_arg = sub_matches.get_one::<String>("MOD").expect("required");
+
+            let updated = instance
+                .set_mod_enabled(mod_arg, enabled)
+                .unwrap_or_else(|e| crash(e));
+
+            if json {
+                print_json(&updated);
+                return;
+            }
+
+            say!(
+                "{} {}",
+                if enabled {
+                    ansi_term::Color::Green.paint("Enabled")
+                } else {
+                    ansi_term::Color::Red.paint("Disabled")
+                },
+                updated.filename
+            );
+        }
+        Some(("copy", sub_matches)) => {
+            let from =
+                PolyMC::find_instance(sub_matches.get_one::<String>("FROM").expect("required"))
+                    .unwrap_or_else(|e| crash(e));
+            let to = PolyMC::find_instance(sub_matches.get_one::<String>("TO").expect("required"))
+                .unwrap_or_else(|e| crash(e));
+            if from.folder_name == to.folder_name {
+                crash("Those are the same instance.".to_string());
+            }
+
+            let report = to.copy_mods_from(&client, &from).await;
+
+            if json {
+                print_json(&report);
+                return;
+            }
+
+            for missing in &report.missing {
+                say!(
+                    "{} {} has no version for {} {}",
+                    ansi_term::Color::Red.paint("Missing"),
+                    ansi_term::Color::Green.paint(missing),
+                    ansi_term::Color::Purple.paint(&to.modloader),
+                    ansi_term::Color::Green.paint(&to.game_version)
+                );
+            }
+            for problem in &report.problems {
+                say!("{} {}", ansi_term::Color::Red.paint("Skipped"), problem);
+            }
+            say!(
+                "{} {} mods into {}",
+                ansi_term::Color::Green.paint("Copied"),
+                report.installed.len(),
+                ansi_term::Color::Blue.paint(&to.name)
+            );
+        }
+        Some(("instance", sub_matches)) => match sub_matches.subcommand() {
+            Some(("create", create_matches)) => {
+                let arg = |name: &str| create_matches.get_one::<String>(name).cloned();
+                let mrpack =
+                    arg("mrpack").map(|path| Mrpack::open(&path).unwrap_or_else(|e| crash(e)));
+                // checked before there's an instance to clean up
+                let manifest = arg("manifest").map(|path| {
+                    let text = std::fs::read_to_string(&path)
+                        .unwrap_or_else(|e| crash(format!("Couldn't read {}: {}", path, e)));
+                    if let Err(e) = toml::from_str::<Manifest>(&text) {
+                        crash(format!("Couldn't read {}: {}", path, e));
+                    }
+                    text
+                });
+
+                let name = arg("name")
+                    .or_else(|| mrpack.as_ref().map(|p| p.index.name.clone()))
+                    .unwrap_or_else(|| crash("The instance needs a --name.".to_string()));
+                let game_version = arg("game")
+                    .or_else(|| {
+                        mrpack
+                            .as_ref()
+                            .and_then(|p| p.game_version().map(|v| v.to_string()))
+                    })
+                    .unwrap_or_else(|| crash("The instance needs a --game version.".to_string()));
+                let (modloader, mut loader_version) = match (arg("loader"), &mrpack) {
+                    (Some(modloader), _) => (modloader, arg("loader-version")),
+                    (None, Some(pack)) => {
+                        let (modloader, pack_version) = pack.loader();
+                        (modloader, arg("loader-version").or(pack_version))
+                    }
+                    (None, None) => ("vanilla".to_string(), None),
+                };
+                if modloader != "vanilla" && loader_version.is_none() {
+                    loader_version = Some(
+                        loader::latest_loader_version(
+                            &loader::meta_base(),
+                            &modloader,
+                            &game_version,
+                        )
+                        .await
+                        .unwrap_or_else(|e| crash(e)),
+                    );
+                }
+
+                let instance = PolyMC::create_instance(
+                    &name,
+                    &game_version,
+                    &modloader,
+                    loader_version.as_deref(),
+                )
+                .unwrap_or_else(|e| crash(e));
+                if !json {
+                    say!(
+                        "{} {} ({} {})",
+                        ansi_term::Color::Green.paint("Created"),
+                        ansi_term::Color::Blue.paint(&instance.name),
+                        ansi_term::Color::Purple.paint(&instance.modloader),
+                        ansi_term::Color::Green.paint(&instance.game_version)
+                    );
+                }
+
+                let installed = match (manifest, &mrpack) {
+                    (Some(text), _) => {
+                        let path = Manifest::get_path(&instance);
+                        match std::fs::write(&path, text) {
+                            Ok(()) => instance.update(&client).await.map_err(|e| e.to_string()),
+                            Err(e) => Err(format!("Couldn't write {}: {}", path, e)),
+                        }
+                    }
+                    (None, Some(mrpack)) => mrpack.install(&client, &instance).await,
+                    (None, None) => Ok(()),
+                };
+                // half an instance is worse than none
+                if let Err(e) = installed {
+                    if let Err(delete_error) = instance.delete() {
+                        crash(format!("{}\n{}", e, delete_error));
+                    }
+                    crash(format!(
+                        "{}\nI deleted {} again.",
+                        e,
+                        ansi_term::Color::Blue.paint(&instance.name)
+                    ));
+                }
+
+                if json {
+                    print_json(&instance);
+                }
+            }
+            _ => unreachable!(),
+        },
+        Some(("diff", sub_matches)) => {
+            let side = |name: &str| {
+                read_diff_side(sub_matches.get_one::<String>(name).expect("required"))
+                    .unwrap_or_else(|e| {
+                        crash(format!(
+                            "Couldn't read the {} side: {}",
+                            name.to_lowercase(),
+                            e
+                        ))
+                    })
+            };
+            let old = side("OLD");
+            let new = side("NEW");
+
+            let mut diff = LockfileDiff::between(&old, &new);
+            diff.fill_titles(&client).await;
+
+            if json {
+                print_json(&diff);
+                return;
+            }
+            if diff.is_empty() {
+                say!("{}", ansi_term::Color::Green.paint("No changes"));
+                return;
+            }
+
+            let print_entries = |sign: ansi_term::ANSIGenericString<str>, entries: &[DiffEntry]| {
+                for entry in entries {
+                    let versions = match (&entry.old_version, &entry.new_version) {
+                        (Some(old), Some(new)) => format!("{} -> {}", old, new),
+                        (Some(version), None) | (None, Some(version)) => version.clone(),
+                        (None, None) => String::new(),
+                    };
+                    say!(
+                        "{} {} {}",
+                        sign,
+                        ansi_term::Color::Green.paint(&entry.title),
+                        ansi_term::Color::RGB(128, 128, 128).paint(versions)
+                    );
+                }
+            };
+            print_entries(ansi_term::Color::Green.paint("+"), &diff.added);
+            print_entries(ansi_term::Color::Red.paint("-"), &diff.removed);
+            print_entries(ansi_term::Color::Purple.paint("~"), &diff.changed);
+        }
+        Some(("migrate-check", sub_matches)) => {
+            let instance =
+                PolyMC::find_instance(sub_matches.get_one::<String>("INSTANCE").expect("required"))
+                    .unwrap_or_else(|e| crash(e));
+            let game_version = sub_matches.get_one::<String>("VERSION").expect("required");
+            let apply = *sub_matches.get_one::<bool>("apply").expect("how");
+
+            let report = instance.migration_check(&client, game_version).await;
+
+            if json {
+                print_json(&report);
+            } else {
+                let print_mods = |label: ansi_term::ANSIGenericString<str>,
+                                  mods: &[MigrationMod]| {
+                    for migration_mod in mods {
+                        say!(
+                            "{} {} {}{}",
+                            label,
+                            ansi_term::Color::Green.paint(&migration_mod.title),
+                            ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                                "({} -> {})",
+                                migration_mod.installed,
+                                migration_mod.available.as_deref().unwrap_or("nothing")
+                            )),
+                            if migration_mod.blocking {
+                                ansi_term::Color::Red.paint(" [blocking]").to_string()
+                            } else {
+                                String::new()
+                            }
+                        );
+                    }
+                };
+                print_mods(ansi_term::Color::Green.paint("Ready    "), &report.ready);
+                print_mods(
+                    ansi_term::Color::Yellow.paint("Only beta"),
+                    &report.only_beta,
+                );
+                print_mods(ansi_term::Color::Red.paint("Missing  "), &report.missing);
+                for problem in &report.problems {
+                    say!("{} {}", ansi_term::Color::Red.paint("Problem  "), problem);
+                }
+
+                say!(
+                    "{} {} ready, {} only beta, {} missing for {} {}",
+                    if report.can_migrate() {
+                        ansi_term::Color::Green.paint("Good to go!")
+                    } else {
+                        ansi_term::Color::Red.paint("Not ready.")
+                    },
+                    report.ready.len(),
+                    report.only_beta.len(),
+                    report.missing.len(),
+                    ansi_term::Color::Purple.paint(&instance.modloader),
+                    ansi_term::Color::Green.paint(game_version)
+                );
+            }
+
+            if !apply {
+                return;
+            }
+            if !report.problems.is_empty() {
+                crash("Some mods couldn't be checked, so nothing was changed.".to_string());
+            }
+            if !report.can_migrate() {
+                crash(
+                    "Some mods you need don't have a version for that yet, so nothing was changed."
+                        .to_string(),
+                );
+            }
+
+            let migrated = instance
+                .migrate(&client, game_version)
                 .await
-                .expect("couldn't update :ldancefast:");
+                .unwrap_or_else(|e| crash(e));
+            if !json {
+                say!(
+                    "{} {} is on {} now",
+                    ansi_term::Color::Green.paint("Migrated!"),
+                    ansi_term::Color::Blue.paint(&migrated.name),
+                    ansi_term::Color::Green.paint(&migrated.game_version)
+                );
+            }
+        }
+        Some(("cache", sub_matches)) => match sub_matches.subcommand() {
+            Some(("info", _)) => {
+                let info = Cache::info();
+
+                if json {
+                    print_json(&info);
+                    return;
+                }
+
+                say!("{}", ansi_term::Color::Blue.paint(&info.directory));
+                say!("{} mod files, {}", info.files, format_size(info.files_size));
+                say!(
+                    "{} API responses, {}",
+                    info.responses,
+                    format_size(info.responses_size)
+                );
+            }
+            Some(("clean", clean_matches)) => {
+                let files = *clean_matches.get_one::<bool>("files").expect("how");
+                let api = *clean_matches.get_one::<bool>("api").expect("how");
+
+                // neither flag means everything
+                Cache::clean(files || !api, api || !files);
+                say!("{}", ansi_term::Color::Green.paint("Cleaned the cache"));
+            }
+            _ => unreachable!(),
+        },
+        Some(("tree", sub_matches)) => {
+            let reverse = sub_matches.get_one::<bool>("reverse").expect("how");
+            let instance = pick_instance(
+                "What instance do you want to look at? ",
+                sub_matches.get_one::<String>("instance"),
+                json,
+            );
+
+            match sub_matches.get_one::<String>("MOD") {
+                Some(mod_arg) if *reverse => {
+                    let tree = DependencyTree::for_instance(&client, &instance).await;
+                    let project_id = match tree.find(mod_arg) {
+                        Some(project_id) => project_id,
+                        None => match MpmMod::new(&client, mod_arg).await {
+                            Ok(data) => data.id,
+                            Err(_) => MpmMod::new_from_hash(&client, mod_arg).await.id,
+                        },
+                    };
+                    tree.print_reverse(&project_id);
+                }
+                Some(mod_arg) => {
+                    let mod_data = match MpmMod::new(&client, mod_arg).await {
+                        Ok(data) => data,
+                        Err(_) => MpmMod::new_from_hash(&client, mod_arg).await,
+                    };
+                    match DependencyTree::for_mod(&client, &mod_data, &instance).await {
+                        Some(tree) => tree.print(),
+                        None => {
+                            crash(format!(
+                                "I couldn't find a version of {} that matches that instance.",
+                                ansi_term::Color::Green.paint(&mod_data.title)
+                            ));
+                        }
+                    }
+                }
+                None => DependencyTree::for_instance(&client, &instance)
+                    .await
+                    .print(),
+            }
         }
         /*
                 Some(("polymc", _)) => {
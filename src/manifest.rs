@@ -0,0 +1,162 @@
+use crate::crash;
+use crate::data_structs::{write_atomically, ModpmLockfile};
+use crate::modrinth::{ModVersion, MpmMod};
+use crate::polymc::PolyInstance;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// The mods you want in an instance, kept in `.minecraft/modpm.toml`. This is what you edit;
+/// the lockfile is what modpm works out from it, like Cargo.toml and Cargo.lock.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// Projects by slug (or id), with whatever they're constrained to.
+    #[serde(default)]
+    pub mods: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ManifestEntry {
+    /// A version id, an exact version number, or a semver range like "^0.5" or ">=0.5, <0.6".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The least stable kind of version that's allowed: "release", "beta", or "alpha".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// "client", "server", or "both". PolyMC instances are clients, so server-only mods are skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub side: Option<String>,
+    /// If nothing matches, leave it out instead of failing.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    /// The world a data pack goes in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world: Option<String>,
+}
+
+impl Manifest {
+    pub fn get_path(instance: &PolyInstance) -> String {
+        format!("{}/modpm.toml", instance.minecraft_directory())
+    }
+
+    /// `None` if the instance doesn't have a manifest yet.
+    pub fn load(instance: &PolyInstance) -> Option<Manifest> {
+        let text = fs::read_to_string(Manifest::get_path(instance)).ok()?;
+
+        Some(toml::from_str(&text).unwrap_or_else(|e| {
+            crash(format!(
+                "Couldn't read {}: {}",
+                Manifest::get_path(instance),
+                e
+            ))
+        }))
+    }
+
+    /// Saves the manifest the same way the lockfile is saved: under the instance's lock, and all
+    /// at once.
+    pub fn save(&self, instance: &PolyInstance) {
+        let _lock = ModpmLockfile::lock(instance);
+        write_atomically(
+            &Manifest::get_path(instance),
+            &toml::to_string_pretty(self).expect("Couldn't serialize the manifest"),
+        )
+        .expect("Couldn't save the manifest");
+    }
+
+    /// Makes a manifest for an instance that only has a lockfile, from the mods that weren't
+    /// installed as dependencies.
+    pub fn from_lockfile(instance: &PolyInstance) -> Manifest {
+        let mut manifest = Manifest::default();
+
+        for installed in ModpmLockfile::get_lockfile(instance.clone()).unwrap_or_else(|e| crash(e))
+        {
+            if installed.dependency_of().is_some() {
+                continue;
+            }
+
+            let world = installed
+                .directory
+                .strip_prefix("saves/")
+                .and_then(|d| d.strip_suffix("/datapacks"))
+                .map(|w| w.to_string());
+
+            manifest.mods.insert(
+                installed.project_id.clone(),
+                ManifestEntry {
+                    world,
+                    ..Default::default()
+                },
+            );
+        }
+
+        manifest
+    }
+
+    /// Adds a mod, or replaces its entry if it's already there under its slug or id.
+    pub fn add(instance: &PolyInstance, mpm_mod: &MpmMod, entry: ManifestEntry) {
+        let mut manifest = Manifest::load(instance).unwrap_or_default();
+
+        manifest.mods.remove(&mpm_mod.id);
+        let key = if mpm_mod.slug.is_empty() {
+            mpm_mod.id.clone()
+        } else {
+            mpm_mod.slug.clone()
+        };
+        manifest.mods.insert(key, entry);
+
+        manifest.save(instance);
+    }
+}
+
+impl ManifestEntry {
+    pub fn wanted_on_client(&self) -> bool {
+        self.side.as_deref() != Some("server")
+    }
+
+    pub fn allows(&self, version: &ModVersion) -> bool {
+        let channel_ok = match self.channel.as_deref() {
+            Some("release") => version.version_type == "release",
+            Some("beta") => version.version_type != "alpha",
+            _ => true,
+        };
+
+        channel_ok
+            && match &self.version {
+                Some(wanted) => version_matches(wanted, version),
+                None => true,
+            }
+    }
+
+    /// The newest version that fits both the instance and this entry's constraints.
+    pub fn pick(&self, mpm_mod: &MpmMod, instance: &PolyInstance) -> Option<ModVersion> {
+        mpm_mod
+            .compatible_versions(instance)
+            .into_iter()
+            .filter(|v| self.allows(v))
+            .min_by_key(|v| (v.loader_rank(instance, &mpm_mod.project_type), -v.time()))
+    }
+}
+
+fn version_matches(wanted: &str, version: &ModVersion) -> bool {
+    if wanted == version.id || wanted == version.version_number {
+        return true;
+    }
+
+    // a bare number is an exact pin, ranges have to say so with an operator
+    if !wanted.starts_with(['<', '>', '=', '^', '~', '*']) {
+        return false;
+    }
+
+    let requirement = match semver::VersionReq::parse(wanted) {
+        Ok(requirement) => requirement,
+        Err(_) => return false,
+    };
+
+    // version numbers on Modrinth are all over the place ("0.5.0", "v0.5.0", "mc1.20.1-0.5.0"),
+    // so try the whole thing and then each part of it
+    let number = version.version_number.trim_start_matches('v');
+    std::iter::once(number)
+        .chain(number.split(['-', '+']))
+        .filter_map(|part| semver::Version::parse(part.trim_start_matches('v')).ok())
+        .any(|v| requirement.matches(&v))
+}
@@ -1,8 +1,17 @@
 use crate::{data_structs::ModpmLockfile, PolyInstance};
 
-use crate::{ask_user, crash, download_file, format_to_vec_of_strings, polymc::PolyMC, web_get};
-use async_recursion::async_recursion;
+use crate::client::ModrinthClient;
+use crate::http::HttpError;
+use crate::loader::{accepted_loaders, check_loader_version, loader_rank};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::resolver::{OptionalDependencies, Resolver};
+use crate::{
+    ask_user, ask_user_with_default, crash, download_file, format_to_vec_of_strings, hash_file,
+    http,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ModVersionFile {
@@ -28,6 +37,11 @@ pub struct ModVersionDependencies {
 pub struct MpmMod {
     pub title: String,
     pub id: String,
+    #[serde(default)]
+    pub slug: String,
+    /// "mod", "resourcepack", "shader", or "datapack".
+    #[serde(default = "default_project_type")]
+    pub project_type: String,
     pub license: ModrinthLicense,
     pub versions: Vec<ModVersion>,
     pub description: String,
@@ -35,6 +49,16 @@ pub struct MpmMod {
     pub source_url: String,
     pub donation_urls: Vec<ModrinthDonationUrls>,
     pub members: Vec<ModrinthTeamMember>,
+    #[serde(default)]
+    pub downloads: u64,
+    #[serde(default)]
+    pub followers: u64,
+    /// Whether the mod is needed on the client: "required", "optional", "unsupported", or "unknown".
+    #[serde(default)]
+    pub client_side: String,
+    /// Same as `client_side`, for servers.
+    #[serde(default)]
+    pub server_side: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -43,6 +67,9 @@ pub struct ModVersion {
     pub id: String,
     pub name: String,
     pub version_number: String,
+    /// "release", "beta", or "alpha".
+    #[serde(default = "default_version_type")]
+    pub version_type: String,
     pub loaders: Vec<String>,
     pub files: Vec<ModVersionFile>,
     pub game_versions: Vec<String>,
@@ -87,6 +114,58 @@ pub struct ModrinthTeamUser {
     pub role: String,
 }
 
+pub fn default_version_type() -> String {
+    "release".to_string()
+}
+
+pub fn default_project_type() -> String {
+    "mod".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModrinthSearchHit {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    pub project_type: String,
+    pub author: String,
+    pub downloads: u64,
+}
+
+/// How `MpmMod::download` should pick what to install.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    /// Always ask which version to install, even if there's only one.
+    pub choose_version: bool,
+    /// A version id, version number, or "latest", to install without asking.
+    pub version: Option<String>,
+    pub optional: OptionalDependencies,
+    /// The world to put a data pack in.
+    pub world: Option<String>,
+}
+
+impl ModVersionDependencies {
+    /// The project this points at. Some only have a version id, which costs a request to look up.
+    pub async fn resolve_project(&self, client: &ModrinthClient) -> String {
+        match &self.project_id {
+            Some(project_id) => project_id.clone(),
+            None => {
+                let version_id = self
+                    .version_id
+                    .clone()
+                    .expect("A dependency didn't have a version ID or a project ID");
+
+                // offline and not cached, so the version id is the best name there is
+                match ModVersion::fetch(client, &version_id).await {
+                    Ok(version) => version.project_id,
+                    Err(_) => version_id,
+                }
+            }
+        }
+    }
+}
+
 impl ModrinthTeamUser {
     pub fn display_name(&self) -> String {
         self.name.clone().unwrap_or(self.username.clone())
@@ -94,46 +173,58 @@ impl ModrinthTeamUser {
 }
 
 impl MpmMod {
-    pub async fn new(query: &str) -> Result<MpmMod, &str> {
-        let data = web_get(&format!("https://api.modrinth.com/v2/project/{}", query)[..])
-            .await
-            .expect("Failed to get the mod data from Modrinth");
-
-        if data.status().as_u16() == 404 {
-            return Err("Couldn't find mod");
-        }
+    pub async fn new<'a>(client: &ModrinthClient, query: &'a str) -> Result<MpmMod, &'a str> {
+        let data = match client.get(&format!("project/{}", query)[..]).await {
+            Ok(data) => data,
+            Err(error) if error.is_not_found() => return Err("Couldn't find mod"),
+            Err(error) => crash(format!(
+                "Failed to get the mod data from Modrinth: {}",
+                error
+            )),
+        };
 
-        let json: serde_json::Value = json5::from_str(&data.text().await.unwrap()[..])
-            .expect("Failed to turn the text into a JSON.");
+        let json: serde_json::Value =
+            json5::from_str(&data[..]).expect("Failed to turn the text into a JSON.");
 
         let title = json["title"].as_str().unwrap();
         let id = json["id"].as_str().unwrap();
+        let slug = json["slug"].as_str().unwrap_or(id);
         let license: ModrinthLicense = json5::from_str(&json["license"].to_string()[..]).unwrap();
         let versions: Vec<ModVersion> = json5::from_str(
-            &web_get(
-                &format!(
-                    "https://api.modrinth.com/v2/versions?ids={:?}",
-                    format_to_vec_of_strings(&json["versions"])
-                )[..],
-            )
-            .await
-            .expect("Couldn't get the mod's versions info from Modrinth.")
-            .text()
-            .await
-            .expect("Couldn't convert Modrinth version info into text.")[..],
+            &client
+                .get(
+                    &format!(
+                        "versions?ids={:?}",
+                        format_to_vec_of_strings(&json["versions"])
+                    )[..],
+                )
+                .await
+                .expect("Couldn't get the mod's versions info from Modrinth.")[..],
         )
         .expect("Couldn't put Modrinth version data into a ModVersion vector.");
 
+        // data packs are listed as mods on Modrinth, with "datapack" as their only loader
+        let mut project_type = json["project_type"].as_str().unwrap_or("mod").to_string();
+        if project_type == "mod"
+            && !versions.is_empty()
+            && versions
+                .iter()
+                .all(|v| v.loaders.iter().all(|l| l == "datapack"))
+        {
+            project_type = "datapack".to_string();
+        }
+
         let description = json["description"].as_str().unwrap();
+        let side = |key: &str| json[key].as_str().unwrap_or("unknown").to_string();
         let categories = format_to_vec_of_strings(&json["categories"]);
         let source_url = json["source_url"].as_str().unwrap();
 
         let donation_urls: Vec<ModrinthDonationUrls> =
             json5::from_str(&json["donation_urls"].to_string()[..]).unwrap();
 
-        let team_url = format!("https://api.modrinth.com/v2/project/{}/members", id);
+        let team_url = format!("project/{}/members", id);
 
-        let team_members_text = web_get(&team_url[..]).await.unwrap().text().await.unwrap();
+        let team_members_text = client.get(&team_url[..]).await.unwrap();
 
         let members: Vec<ModrinthTeamMember> = json5::from_str(&team_members_text[..])
             .expect("Couldn't turn team members into the ModrinthTeamMember struct");
@@ -141,6 +232,8 @@ impl MpmMod {
         Ok(MpmMod {
             title: title.to_string(),
             id: id.to_string(),
+            slug: slug.to_string(),
+            project_type,
             license,
             versions,
             description: description.to_string(),
@@ -148,228 +241,500 @@ impl MpmMod {
             donation_urls,
             source_url: source_url.to_string(),
             members,
+            downloads: json["downloads"].as_u64().unwrap_or_default(),
+            followers: json["followers"].as_u64().unwrap_or_default(),
+            client_side: side("client_side"),
+            server_side: side("server_side"),
         })
     }
 
-    pub async fn new_from_hash(hash: &str) -> MpmMod {
-        let query_str = format!(
-            "https://api.modrinth.com/v2/version_file/{}?algorithm=sha512",
-            hash
-        );
+    pub async fn new_from_hash(client: &ModrinthClient, hash: &str) -> MpmMod {
+        let json = match ModVersion::from_hash(client, hash).await {
+            Ok(version) => version,
+            Err(error) if error.is_not_found() => {
+                crash("Couldn't get a mod's version from it's hash.".to_string())
+            }
+            Err(error) => crash(format!(
+                "Failed to get a mod's version from Modrinth: {}",
+                error
+            )),
+        };
 
-        let query = web_get(&query_str[..]).await.unwrap();
-        if query.status().as_u16() == 404 {
-            crash("Couldn't get a mod's version from it's hash.".to_string());
+        MpmMod::new(client, &json.project_id[..]).await.unwrap()
+    }
+
+    /// Searches Modrinth, only showing projects with a version for `instance` if one is given.
+    pub async fn search(
+        client: &ModrinthClient,
+        query: &str,
+        instance: Option<&PolyInstance>,
+    ) -> Vec<ModrinthSearchHit> {
+        let mut params = vec![("query", query.to_string()), ("limit", "20".to_string())];
+        if let Some(instance) = instance {
+            params.push((
+                "facets",
+                format!(
+                    "[[\"versions:{}\"],[{}]]",
+                    instance.game_version,
+                    accepted_loaders("mod", instance)
+                        .iter()
+                        .map(|l| format!("\"categories:{}\"", l))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                ),
+            ));
         }
 
-        let json: ModVersion = json5::from_str(&query.text().await.unwrap()[..]).unwrap();
+        let url = reqwest::Url::parse_with_params(&client.url("search"), &params)
+            .expect("Couldn't build a search URL");
 
-        MpmMod::new(&json.project_id[..]).await.unwrap()
-    }
+        let results_text = match http::get(url.as_str()).await {
+            Ok(response) => response
+                .text()
+                .await
+                .expect("Couldn't convert Modrinth search results into text."),
+            Err(error) => crash(format!("Couldn't search Modrinth: {}", error)),
+        };
 
-    pub fn get_owner(&self) -> Option<&ModrinthTeamMember> {
-        let members = &self.members;
-        members.into_iter().find(|m| m.role == "Owner")
-    }
+        let results: serde_json::Value =
+            json5::from_str(&results_text[..]).expect("Failed to turn the text into a JSON.");
 
-    pub async fn download(&self, instance: PolyInstance, choose_version: bool) {
-        let versions_base = &self.versions;
+        serde_json::from_value(results["hits"].clone())
+            .expect("Couldn't put Modrinth search results into a ModrinthSearchHit vector.")
+    }
 
-        // all the versions of the mod that work with the modloader and current game version
-        let versions_filtered = versions_base.into_iter().filter(|v| {
-            v.game_versions.contains(&instance.game_version)
-                && v.loaders.contains(&instance.modloader)
-        });
+    /// Looks up the titles of a bunch of projects in one request.
+    pub async fn get_titles(client: &ModrinthClient, ids: &[String]) -> HashMap<String, String> {
+        let mut titles = HashMap::new();
+        if ids.is_empty() {
+            return titles;
+        }
 
-        // i blame rust for this
-        let mut possible_versions: Vec<ModVersion> = vec![];
+        // titles are only for show, so going without them beats giving up
+        let projects_text = match client.get(&format!("projects?ids={:?}", ids)[..]).await {
+            Ok(projects_text) => projects_text,
+            Err(_) => return titles,
+        };
 
-        for version in versions_filtered {
-            possible_versions.push(version.clone());
-        }
+        let projects: serde_json::Value =
+            json5::from_str(&projects_text[..]).expect("Failed to turn the text into a JSON.");
 
-        // get the latest version's timestamp
-        let mut latest_version_timestamp = 0;
-        for version in &possible_versions {
-            if version.time() > latest_version_timestamp {
-                latest_version_timestamp = version.time()
+        if let Some(projects) = projects.as_array() {
+            for project in projects {
+                if let (Some(id), Some(title)) = (project["id"].as_str(), project["title"].as_str())
+                {
+                    titles.insert(id.to_string(), title.to_string());
+                }
             }
         }
 
-        // get the latest *version*
-        let latest_version = possible_versions
-            .clone()
+        titles
+    }
+
+    /// Versions for a game version and/or loader, newest first. `None` doesn't filter.
+    pub fn filter_versions(
+        &self,
+        game_version: Option<&str>,
+        loader: Option<&str>,
+    ) -> Vec<ModVersion> {
+        let mut versions: Vec<ModVersion> = self
+            .versions
+            .iter()
+            .filter(|v| game_version.is_none_or(|g| v.game_versions.iter().any(|v| v == g)))
+            .filter(|v| loader.is_none_or(|l| v.loaders.iter().any(|v| v == l)))
+            .cloned()
+            .collect();
+        versions.sort_by_key(|v| -v.time());
+        versions
+    }
+
+    pub fn get_owner(&self) -> Option<&ModrinthTeamMember> {
+        self.members.iter().find(|m| m.role == "Owner")
+    }
+
+    /// All versions of the mod that work with the instance's modloader and game version.
+    pub fn compatible_versions(&self, instance: &PolyInstance) -> Vec<ModVersion> {
+        self.versions
+            .iter()
+            .filter(|v| v.supports(instance, &self.project_type))
+            .cloned()
+            .collect()
+    }
+
+    /// The newest version that works with the instance, preferring builds for the instance's own
+    /// modloader over ones that only run on it through compatibility.
+    pub fn latest_compatible_version(&self, instance: &PolyInstance) -> Option<ModVersion> {
+        self.compatible_versions(instance)
             .into_iter()
-            .find(|v| v.time() == latest_version_timestamp)
-            .unwrap();
+            .min_by_key(|v| (v.loader_rank(instance, &self.project_type), -v.time()))
+    }
 
-        let version_to_download: ModVersion;
+    pub async fn download(
+        &self,
+        client: &ModrinthClient,
+        instance: PolyInstance,
+        options: DownloadOptions,
+    ) {
+        ModpmLockfile::with_instance_locked(&instance, async {
+            let mut picked_world = None;
+            let directory = match &self.project_type[..] {
+                "datapack" => {
+                    let worlds = instance.get_worlds();
+                    if worlds.is_empty() {
+                        crash(
+                            "That instance doesn't have any worlds to put a data pack in.".to_string(),
+                        );
+                    }
 
-        let len;
-        if choose_version {
-            len = 2;
-        } else {
-            len = possible_versions.clone().len()
-        }
+                    let world = match options.world {
+                        // a typo would make a new saves folder that isn't a world at all
+                        Some(world) if !worlds.contains(&world) => crash(format!(
+                            "{} doesn't have a world called {}. Its worlds are: {}",
+                            ansi_term::Color::Blue.paint(&instance.name),
+                            ansi_term::Color::Green.paint(&world),
+                            worlds.join(", ")
+                        )),
+                        Some(world) => world,
+                        None => {
+                            for (num, world) in worlds.iter().enumerate() {
+                                crate::say!("{}: {}", num + 1, ansi_term::Color::Green.paint(world));
+                            }
+                            let world_id =
+                                ask_user("What world do you want to add this data pack to? ");
+                            world_id
+                                .parse::<usize>()
+                                .ok()
+                                .and_then(|n| worlds.get(n.wrapping_sub(1)).cloned())
+                                .unwrap_or_else(|| {
+                                    crash(format!("There's no world number {}.", world_id.trim()))
+                                })
+                        }
+                    };
+                    let directory = PolyInstance::content_directory(&self.project_type, Some(&world));
+                    picked_world = Some(world);
+                    directory
+                }
+                _ => PolyInstance::content_directory(&self.project_type, None),
+            };
 
-        match len {
-            // if there's no versions that work with the instance
-            0 => {
+            // the lockfile (and modpm.toml) hold one copy of a project, so a data pack lives in one world
+            if let Some(installed) = ModpmLockfile::get_lockfile(instance.clone())
+.unwrap_or_else(|e| crash(e))
+                .into_iter()
+                .find(|m| m.project_id == self.id && m.directory != directory)
+            {
                 crash(format!(
-                    "I couldn't find a version of {} that matches that instance.",
-                    ansi_term::Color::Green.paint(&self.title)
+                    "{} is already installed in {}, and modpm only keeps one copy of it per instance. To move it, change its world in modpm.toml and run {}.",
+                    ansi_term::Color::Green.paint(&self.title),
+                    installed.directory,
+                    ansi_term::Color::RGB(128, 128, 128).paint("modpm update")
                 ));
-
-                version_to_download = ModVersion {
-                    mpm_id: None,
-                    id: String::new(),
-                    name: String::new(),
-                    version_number: String::new(),
-                    project_id: String::new(),
-                    date_published: String::from("1970-01-01T00:00:00Z"),
-                    files: vec![],
-                    game_versions: vec![],
-                    loaders: vec![],
-                    dependencies: vec![],
-                }
             }
 
-            // if there's one version that works with the instance
-            1 => version_to_download = possible_versions[0].clone(),
-
-            // if there's any other number of versions that work with the instance
-            _ => {
-                let mut num = 0;
-                let mut versions_with_id: Vec<ModVersion> = vec![];
-                for mut v in possible_versions {
-                    num += 1;
-                    v.mpm_id = Some(num);
-                    versions_with_id.push(v);
+            let possible_versions = self.compatible_versions(&instance);
+
+            let version_to_download: ModVersion = match self.latest_compatible_version(&instance) {
+                // if there's no versions that work with the instance
+                None if options.version.is_none() => {
+                    crash(format!(
+                        "I couldn't find a version of {} that matches that instance.",
+                        ansi_term::Color::Green.paint(&self.title)
+                    ));
                 }
 
-                for version in &versions_with_id {
-                    println!(
-                        "{}: {} ({}{})",
-                        version.mpm_id.expect("A mod version didn't have an ID"),
-                        ansi_term::Color::Green.paint(&version.name),
-                        ansi_term::Color::RGB(128, 128, 128).paint(&version.version_number),
-                        ansi_term::Color::Red.paint(
-                            if version.version_number == latest_version.version_number {
-                                " latest"
-                            } else {
-                                ""
+                // if a version was picked on the command line
+                latest_version if options.version.is_some() => {
+                    match options.version.as_deref().expect("how") {
+                        "latest" => match latest_version {
+                            Some(latest_version) => latest_version,
+                            None => {
+                                crash(format!(
+                                    "I couldn't find a version of {} that matches that instance.",
+                                    ansi_term::Color::Green.paint(&self.title)
+                                ));
                             }
-                        ),
-                    );
+                        },
+                        // several loaders' builds can share a version number, so take the best fit
+                        wanted => match possible_versions
+                            .iter()
+                            .filter(|v| v.id == wanted || v.version_number == wanted)
+                            .min_by_key(|v| v.loader_rank(&instance, &self.project_type))
+                        {
+                            Some(version) => version.clone(),
+                            None => match self
+                                .versions
+                                .iter()
+                                .find(|v| v.id == wanted || v.version_number == wanted)
+                            {
+                                Some(version) => crash(format!(
+                                    "{} {} is for {} on {}, which doesn't fit this instance ({} {}).",
+                                    ansi_term::Color::Green.paint(&self.title),
+                                    version.version_number,
+                                    version.loaders.join("/"),
+                                    version.game_versions.join(", "),
+                                    instance.modloader,
+                                    instance.game_version
+                                )),
+                                None => crash(format!(
+                                    "{} doesn't have a version called {}.",
+                                    ansi_term::Color::Green.paint(&self.title),
+                                    wanted
+                                )),
+                            },
+                        },
+                    }
                 }
 
-                let version_id = ask_user("What version of this mod do you want to download? ");
-
-                version_to_download = versions_with_id
-                    .into_iter()
-                    .find(|i| i.mpm_id.unwrap().to_string() == version_id)
-                    .expect("Couldn't find that instance.");
-            }
-        };
-
-        if ModpmLockfile::get_lockfile(instance.clone())
-            .into_iter()
-            .find(|v| v.version.project_id == version_to_download.project_id)
-            .is_some()
-        {
-            crash(format!("you've already downloaded that mod from modpm in this instance - if you wanted to update it, please run {}.", ansi_term::Color::RGB(128,128,128).paint("modpm update")));
-        }
+                // if there's one version that works with the instance
+                Some(latest_version) if !options.choose_version && possible_versions.len() == 1 => {
+                    latest_version
+                }
 
-        MpmMod::download_specific_version(version_to_download.clone(), &instance).await;
-    }
+                // if there's any other number of versions that work with the instance
+                Some(latest_version) => {
+                    let mut latest_id = String::new();
+                    let mut num = 0;
+                    let mut versions_with_id: Vec<ModVersion> = vec![];
+                    for mut v in possible_versions {
+                        num += 1;
+                        v.mpm_id = Some(num);
+                        versions_with_id.push(v);
+                    }
 
-    #[async_recursion]
-    pub async fn download_specific_version(version: ModVersion, instance: &PolyInstance) {
-        let file_to_download: ModVersionFile = if version.files.len() == 1 {
-            version.files[0].clone()
-        } else {
-            version
-                .clone()
-                .files
-                .into_iter()
-                .find(|f| f.primary == true)
-                .expect("Couldn't find a mod version's primary file")
-        };
+                    for version in &versions_with_id {
+                        if version.id == latest_version.id {
+                            latest_id = version.mpm_id.expect("how").to_string();
+                        }
+                        crate::say!(
+                            "{}: {} ({}{})",
+                            version.mpm_id.expect("A mod version didn't have an ID"),
+                            ansi_term::Color::Green.paint(&version.name),
+                            ansi_term::Color::RGB(128, 128, 128).paint(&version.version_number),
+                            ansi_term::Color::Red.paint(
+                                if version.version_number == latest_version.version_number {
+                                    " latest"
+                                } else {
+                                    ""
+                                }
+                            ),
+                        );
+                    }
 
-        let path = format!(
-            "{}/instances/{}/.minecraft/mods",
-            PolyMC::get_directory(),
-            instance.folder_name
-        );
+                    let version_id = ask_user_with_default(
+                        "What version of this mod do you want to download? ",
+                        &latest_id,
+                    );
 
-        let i_hate_rust = file_to_download.clone();
+                    versions_with_id
+                        .into_iter()
+                        .find(|i| i.mpm_id.unwrap().to_string() == version_id)
+                        .expect("Couldn't find that version.")
+                }
+                None => unreachable!(),
+            };
 
-        println!("Downloading {}", file_to_download.filename);
-        download_file(file_to_download.url, path, file_to_download.filename)
-            .await
-            .expect("Failed to download a mod file");
+            // anything other than the newest version was picked on purpose, so it stays pinned
+            let manifest_entry = ManifestEntry {
+                version: match self.latest_compatible_version(&instance) {
+                    Some(latest) if latest.id == version_to_download.id => None,
+                    _ => Some(version_to_download.version_number.clone()),
+                },
+                world: picked_world,
+                ..Default::default()
+            };
 
-        ModpmLockfile::add_to_lockfile(instance.clone(), &version, &i_hate_rust);
+            if let Some(installed) = ModpmLockfile::get_lockfile(instance.clone())
+.unwrap_or_else(|e| crash(e))
+                .into_iter()
+                .find(|v| v.project_id == version_to_download.project_id)
+            {
+                if installed.version_id == version_to_download.id {
+                    Manifest::add(&instance, self, manifest_entry);
+                    eprintln!("{} {} is already installed in this instance - if you wanted to update it, please run {}.", ansi_term::Color::Green.paint(&self.title), ansi_term::Color::RGB(128, 128, 128).paint(&installed.version_number), ansi_term::Color::RGB(128,128,128).paint("modpm update"));
+                    return;
+                }
+            }
 
-        for dep in version.dependencies {
-            if dep.dependency_type == "required" {
-                let new_dep: Option<ModVersion> = if dep.version_id.is_some() {
-                    Some(ModVersion::new(dep.version_id.expect("how")).await)
-                } else {
-                    println!(
+            let resolution = match Resolver::new(client, &instance, options.optional)
+                .resolve(&self.title, version_to_download)
+                .await
+            {
+                Ok(resolution) => resolution,
+                Err(conflicts) => {
+                    eprintln!(
                         "{}",
-                        ansi_term::Color::RGB(128, 128, 128)
-                            .paint("No version specified, looking for latest compatible version")
+                        ansi_term::Color::Red
+                            .paint("I couldn't find a set of mods that work together:")
                     );
-                    let fuck = MpmMod::new(
-                        &dep.project_id
-                            .expect("A dependency didn't have a version ID or a project ID"),
+                    for conflict in conflicts {
+                        eprintln!("  - {}", conflict);
+                    }
+                    crash("Nothing was downloaded.".to_string());
+                }
+            };
+
+            for cycle in &resolution.cycles {
+                eprintln!(
+                    "{}",
+                    ansi_term::Color::RGB(128, 128, 128)
+                        .paint(format!("Dependency cycle found: {}", cycle.join(" -> ")))
+                );
+            }
+
+            for version in resolution.versions {
+                let dependency_of = resolution.parents.get(&version.project_id).cloned();
+
+                // dependencies are always mods, whatever pulled them in
+                let downloaded = if version.project_id == self.id {
+                    MpmMod::download_specific_version(
+                        version,
+                        &instance,
+                        &self.project_type,
+                        &directory,
+                        dependency_of,
                     )
                     .await
-                    .expect("Couldn't fetch a dependency's project");
+                } else {
+                    MpmMod::download_specific_version(version, &instance, "mod", "mods", dependency_of)
+                        .await
+                };
+                if let Err(e) = downloaded {
+                    crash(e);
+                }
+            }
 
-                    let mut possible_versions: Vec<ModVersion> = vec![];
+            Manifest::add(&instance, self, manifest_entry);
+        })
+        .await
+    }
 
-                    for aaa in &fuck.versions {
-                        let supports_modloader = aaa.loaders.contains(&instance.modloader);
-                        let supports_version = aaa.game_versions.contains(&instance.game_version);
+    pub async fn download_specific_version(
+        version: ModVersion,
+        instance: &PolyInstance,
+        project_type: &str,
+        directory: &str,
+        dependency_of: Option<String>,
+    ) -> Result<(), String> {
+        let file_to_download: ModVersionFile = version.primary_file();
+
+        let path = format!("{}/{}", instance.minecraft_directory(), directory);
+        fs::create_dir_all(&path).map_err(|e| format!("Couldn't create {}: {}", path, e))?;
+
+        // download next to where it's going first, so a failed download leaves the old version alone
+        let part_name = format!(".{}.part", file_to_download.filename);
+        eprintln!("Downloading {}", file_to_download.filename);
+        let downloaded = download_file(
+            file_to_download.url.clone(),
+            path.clone(),
+            part_name.clone(),
+            &file_to_download.hashes.sha512,
+        )
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|_| match hash_file(&format!("{}/{}", path, part_name)) {
+            Ok(hash) if hash == file_to_download.hashes.sha512 => Ok(()),
+            Ok(_) => Err("it didn't match Modrinth's hash".to_string()),
+            Err(e) => Err(e.to_string()),
+        });
+        if let Err(e) = downloaded {
+            fs::remove_file(format!("{}/{}", path, part_name)).ok();
+            return Err(format!(
+                "Failed to download {}: {}",
+                file_to_download.filename, e
+            ));
+        }
 
-                        if supports_version && supports_modloader {
-                            possible_versions.push(aaa.clone());
-                        };
-                    }
+        // Modrinth doesn't know which loader versions a mod needs, only the mod itself does
+        if let (true, Some(loader_version)) = (project_type == "mod", &instance.modloader_version) {
+            if let Err(needs) = check_loader_version(
+                &format!("{}/{}", path, part_name),
+                &instance.modloader,
+                loader_version,
+            ) {
+                fs::remove_file(format!("{}/{}", path, part_name)).ok();
+                return Err(format!(
+                    "{} needs {} {}, but this instance has {}. Update the loader in PolyMC, or pick an older version.",
+                    file_to_download.filename, instance.modloader, needs, loader_version
+                ));
+            }
+        }
 
-                    if possible_versions.is_empty() {
-                        println!("I couldn't find any versions of {} that support the instance you're trying to download it into. {}, as this dependency was marked as required!", fuck.title, ansi_term::Color::Red.paint("This instance probably won't launch"));
-                        None
-                    } else {
-                        // find latest version
-                        Some(possible_versions.last().expect("how").clone())
-                    }
-                };
+        // swap out whatever version of this mod was there before, so there's never two jars
+        let new_path = format!("{}/{}", path, file_to_download.filename);
+        fs::rename(format!("{}/{}", path, part_name), &new_path)
+            .map_err(|e| format!("Couldn't move {} into place: {}", new_path, e))?;
+
+        let old = ModpmLockfile::add_to_lockfile(
+            instance.clone(),
+            &version,
+            &file_to_download,
+            project_type,
+            directory,
+            dependency_of,
+        );
+        if let Some(old) = &old {
+            let old_path = format!("{}/{}", instance.minecraft_directory(), old.relative_path());
+            if old_path != new_path {
+                fs::remove_file(old_path).ok();
+            }
+        }
 
-                if new_dep.is_some() {
-                    MpmMod::download_specific_version(new_dep.expect("how"), &instance).await
-                }
-            };
+        // a disabled mod stays disabled when it changes version
+        if old.is_some_and(|old| old.disabled) {
+            ModpmLockfile::set_disabled(instance, &version.project_id, true)?;
         }
+
+        Ok(())
     }
 }
 
 impl ModVersion {
-    pub async fn new(id: String) -> ModVersion {
-        let version_string = web_get(&format!("https://api.modrinth.com/v2/version/{}", id)[..])
-            .await
-            .expect("Couldn't get a version")
-            .text()
+    pub async fn new(client: &ModrinthClient, id: String) -> ModVersion {
+        ModVersion::fetch(client, &id)
             .await
-            .expect("Couldn't get a version's text data");
+            .unwrap_or_else(|e| crash(format!("Couldn't get a version: {}", e)))
+    }
+
+    pub async fn fetch(client: &ModrinthClient, id: &str) -> Result<ModVersion, HttpError> {
+        let version_string = client.get(&format!("version/{}", id)[..]).await?;
 
         let version: ModVersion = json5::from_str(&version_string)
             .expect("Couldn't turn a version's JSON data into a ModVersion struct");
 
-        version
+        Ok(version)
+    }
+
+    /// The version a file belongs to, from its sha512.
+    pub async fn from_hash(client: &ModrinthClient, sha512: &str) -> Result<ModVersion, HttpError> {
+        let version_string = client
+            .get(&format!("version_file/{}?algorithm=sha512", sha512)[..])
+            .await?;
+
+        Ok(json5::from_str(&version_string)
+            .expect("Couldn't turn a version's JSON data into a ModVersion struct"))
+    }
+
+    pub fn supports(&self, instance: &PolyInstance, project_type: &str) -> bool {
+        self.game_versions.contains(&instance.game_version)
+            && self.loader_rank(instance, project_type).is_some()
+    }
+
+    pub fn loader_rank(&self, instance: &PolyInstance, project_type: &str) -> Option<usize> {
+        loader_rank(&self.loaders, &accepted_loaders(project_type, instance))
+    }
+
+    pub fn primary_file(&self) -> ModVersionFile {
+        if self.files.len() == 1 {
+            self.files[0].clone()
+        } else {
+            self.files
+                .iter()
+                .find(|f| f.primary)
+                .expect("Couldn't find a mod version's primary file")
+                .clone()
+        }
     }
+
     pub fn time(&self) -> i64 {
         use chrono::prelude::*;
 
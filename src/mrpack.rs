@@ -0,0 +1,224 @@
+use crate::client::ModrinthClient;
+use crate::data_structs::ModpmLockfile;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::modrinth::{ModVersion, MpmMod};
+use crate::polymc::PolyInstance;
+use crate::{download_file, hash_file};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path};
+
+/// A Modrinth modpack, the `.mrpack` kind.
+pub struct Mrpack {
+    path: String,
+    pub index: MrpackIndex,
+}
+
+/// `modrinth.index.json`, from the root of the pack.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MrpackIndex {
+    pub format_version: u32,
+    pub game: String,
+    pub name: String,
+    pub files: Vec<MrpackFile>,
+    /// "minecraft" and the modloader, like "fabric-loader", to their versions.
+    pub dependencies: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MrpackFile {
+    /// Where it goes, relative to `.minecraft`.
+    pub path: String,
+    pub hashes: MrpackHashes,
+    pub env: Option<MrpackEnv>,
+    pub downloads: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MrpackHashes {
+    pub sha512: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MrpackEnv {
+    pub client: String,
+}
+
+impl Mrpack {
+    pub fn open(path: &str) -> Result<Mrpack, String> {
+        let mut archive = open_archive(path)?;
+        let index_file = archive
+            .by_name("modrinth.index.json")
+            .map_err(|_| format!("{} doesn't have a modrinth.index.json", path))?;
+        let index: MrpackIndex = serde_json::from_reader(index_file)
+            .map_err(|e| format!("Couldn't read {}'s modrinth.index.json: {}", path, e))?;
+
+        if index.game != "minecraft" || index.format_version != 1 {
+            return Err(format!("{} isn't a modpack I know how to read", path));
+        }
+
+        Ok(Mrpack {
+            path: path.to_string(),
+            index,
+        })
+    }
+
+    pub fn game_version(&self) -> Option<&str> {
+        self.index.dependencies.get("minecraft").map(|v| &v[..])
+    }
+
+    /// The modloader the pack wants and its version, or "vanilla" if it doesn't want one.
+    pub fn loader(&self) -> (String, Option<String>) {
+        for (key, loader) in [
+            ("fabric-loader", "fabric"),
+            ("quilt-loader", "quilt"),
+            ("forge", "forge"),
+            ("neoforge", "neoforge"),
+        ] {
+            if let Some(version) = self.index.dependencies.get(key) {
+                return (loader.to_string(), Some(version.clone()));
+            }
+        }
+
+        ("vanilla".to_string(), None)
+    }
+
+    /// Puts the pack into an instance, with exactly the files the pack has. Files that are on
+    /// Modrinth go in the lockfile, and get pinned to that version in modpm.toml; anything else is
+    /// just downloaded.
+    pub async fn install(
+        &self,
+        client: &ModrinthClient,
+        instance: &PolyInstance,
+    ) -> Result<(), String> {
+        ModpmLockfile::with_instance_locked(instance, async {
+            for file in &self.index.files {
+                if file.env.as_ref().is_some_and(|e| e.client == "unsupported") {
+                    continue;
+                }
+                if !is_safe_path(&file.path) {
+                    return Err(format!("The pack wants to put a file at {}", file.path));
+                }
+
+                let (directory, filename) = match file.path.rsplit_once('/') {
+                    Some((directory, filename)) => (directory.to_string(), filename.to_string()),
+                    None => (String::new(), file.path.clone()),
+                };
+
+                if let Ok(mut version) = ModVersion::from_hash(client, &file.hashes.sha512).await {
+                    let mpm_mod = MpmMod::new(client, &version.project_id)
+                        .await
+                        .map_err(|e| format!("Couldn't fetch {}: {}", file.path, e))?;
+
+                    // update would put it somewhere else, so it can't manage this one
+                    if mpm_mod.project_type != "datapack"
+                        && PolyInstance::content_directory(&mpm_mod.project_type, None) == directory
+                    {
+                        // the pack might want one of the version's other files
+                        for version_file in &mut version.files {
+                            version_file.primary = version_file.hashes.sha512 == file.hashes.sha512;
+                        }
+                        let version_id = version.id.clone();
+
+                        MpmMod::download_specific_version(
+                            version,
+                            instance,
+                            &mpm_mod.project_type,
+                            &directory,
+                            None,
+                        )
+                        .await?;
+                        Manifest::add(
+                            instance,
+                            &mpm_mod,
+                            ManifestEntry {
+                                version: Some(version_id),
+                                ..Default::default()
+                            },
+                        );
+                        continue;
+                    }
+                }
+
+                let url = file.downloads.first().ok_or_else(|| {
+                    format!("{} doesn't have anywhere to download it from", file.path)
+                })?;
+                let path = format!("{}/{}", instance.minecraft_directory(), directory);
+                fs::create_dir_all(&path)
+                    .map_err(|e| format!("Couldn't create {}: {}", path, e))?;
+
+                eprintln!("Downloading {}", filename);
+                download_file(
+                    url.clone(),
+                    path.clone(),
+                    filename.clone(),
+                    &file.hashes.sha512,
+                )
+                .await
+                .map_err(|e| format!("Failed to download {}: {}", file.path, e))?;
+
+                let destination = format!("{}/{}", path, filename);
+                if hash_file(&destination).ok().as_deref() != Some(&file.hashes.sha512[..]) {
+                    fs::remove_file(&destination).ok();
+                    return Err(format!("{} didn't match the pack's hash", file.path));
+                }
+            }
+
+            self.extract_overrides(instance)
+        })
+        .await
+    }
+
+    /// Copies `overrides/`, then `client-overrides/` on top, into `.minecraft`.
+    fn extract_overrides(&self, instance: &PolyInstance) -> Result<(), String> {
+        let mut archive = open_archive(&self.path)?;
+
+        for prefix in ["overrides", "client-overrides"] {
+            for num in 0..archive.len() {
+                let mut entry = archive
+                    .by_index(num)
+                    .map_err(|e| format!("Couldn't read {}: {}", self.path, e))?;
+                // enclosed_name refuses anything that'd end up outside the folder
+                let relative = match entry
+                    .enclosed_name()
+                    .and_then(|p| p.strip_prefix(prefix).ok().map(|p| p.to_path_buf()))
+                {
+                    Some(relative) if relative.as_os_str().is_empty() => continue,
+                    Some(relative) => relative,
+                    None => continue,
+                };
+
+                let destination = Path::new(&instance.minecraft_directory()).join(relative);
+                if entry.is_dir() {
+                    fs::create_dir_all(&destination).ok();
+                    continue;
+                }
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent).ok();
+                }
+
+                let mut out = File::create(&destination)
+                    .map_err(|e| format!("Couldn't create {}: {}", destination.display(), e))?;
+                io::copy(&mut entry, &mut out)
+                    .map_err(|e| format!("Couldn't write {}: {}", destination.display(), e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn open_archive(path: &str) -> Result<zip::ZipArchive<File>, String> {
+    let file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
+    zip::ZipArchive::new(file).map_err(|e| format!("{} isn't a zip file: {}", path, e))
+}
+
+/// Packs aren't allowed to put files outside `.minecraft`.
+fn is_safe_path(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
@@ -1,6 +1,15 @@
-use crate::{parse_cfg_file, crash, data_structs::ModpmLockfile, modrinth::MpmMod};
-use std::{error::Error, path::Path, env, fs};
-use serde_derive::{Serialize, Deserialize};
+use crate::{
+    client::ModrinthClient,
+    crash,
+    data_structs::{installed_files_path, write_atomically, LockfileMod, ModpmLockfile},
+    download_file, hash_file,
+    manifest::Manifest,
+    modrinth::{ModVersion, MpmMod},
+    parse_cfg_file,
+    resolver::{OptionalDependencies, Resolver},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::HashMap, env, error::Error, fs, path::Path};
 
 pub struct PolyMC {}
 
@@ -38,26 +47,22 @@ impl PolyMC {
                 }
                 crash("The OS is linux, but neither the default nor the flatpak PolyMC folder locations could be found".to_string())
             }
-            "macos" => {
-                return format!(
-                    "{}/Library/Application Support/PolyMC",
-                    env::var("HOME").expect("Couldn't get the $HOME env var.")
-                )
-            }
+            "macos" => format!(
+                "{}/Library/Application Support/PolyMC",
+                env::var("HOME").expect("Couldn't get the $HOME env var.")
+            ),
             "windows" => {
                 // windows <:hollow:829582572983943209>
                 // this os has so many problems with it i stg
-                return format!(
+                format!(
                     "{}\\AppData\\Roaming\\PolyMC",
                     env::var("USERPROFILE").expect("Couldn't get the $USERPROFILE env var.")
                 )
             }
-            _ => {
-                return format!(
-                    "{}/.local/share/PolyMC",
-                    env::var("HOME").expect("Couldn't get the $HOME env var.")
-                )
-            }
+            _ => format!(
+                "{}/.local/share/PolyMC",
+                env::var("HOME").expect("Couldn't get the $HOME env var.")
+            ),
         }
     }
 
@@ -72,23 +77,22 @@ impl PolyMC {
 
         let mut return_instances: Vec<PolyInstance> = vec![];
         let mut num = 0;
-        let instance_dirs_wtf = fs::read_dir(&format!("{}/instances", poly_dir))?;
+        let instance_dirs_wtf = fs::read_dir(format!("{}/instances", poly_dir))?;
         let mut instance_dirs = vec![];
         for dir in instance_dirs_wtf {
             instance_dirs.push(dir.unwrap());
         }
 
-        instance_dirs = instance_dirs
-            .into_iter()
-            .filter(|t| {
-                t.file_name() != ".LAUNCHER_TEMP"
-                    && t.file_name() != "_LAUNCHER_TEMP"
-                    && t.file_type().unwrap().is_dir()
-            })
-            .collect();
+        instance_dirs.retain(|t| {
+            t.file_name() != ".LAUNCHER_TEMP"
+                && t.file_name() != "_LAUNCHER_TEMP"
+                && t.file_type().unwrap().is_dir()
+        });
+        // read_dir doesn't promise any order
+        instance_dirs.sort_by_key(|d| d.file_name());
 
         for dir in instance_dirs {
-            num = num + 1;
+            num += 1;
             let instance_config = parse_cfg_file(format!("{}/instance.cfg", dir.path().display()));
             let mmc_pack: PolyInstanceDataJson = serde_json::from_str(
                 &fs::read_to_string(format!("{}/mmc-pack.json", dir.path().display()))
@@ -98,16 +102,14 @@ impl PolyMC {
 
             let instance_components = &mmc_pack.components;
             let game_version = &instance_components
-                .into_iter()
+                .iter()
                 .find(|c| c.uid == "net.minecraft")
                 .expect("Couldn't find a Minecraft component in a PolyMC instance.")
                 .version;
 
-            let modloader_id_option = instance_components.into_iter().find(|c| {
-                c.uid == "net.fabricmc.fabric-loader"
-                    || c.uid == "org.quiltmc.quilt-loader"
-                    || c.uid == "net.minecraftforge"
-            });
+            let modloader_id_option = instance_components
+                .iter()
+                .find(|c| PolyMC::get_loader_name(&c.uid).is_some());
 
             let instance_name = instance_config
                 .get("name")
@@ -120,17 +122,159 @@ impl PolyMC {
             };
 
             return_instances.push(PolyInstance {
-                id: num,        
+                id: num,
                 name: instance_name.to_string(),
                 modloader: modloader_id.to_string(),
+                modloader_version: modloader_id_option.map(|c| c.version.clone()),
                 game_version: game_version.to_string(),
                 folder_name: dir.file_name().to_str().expect("something went wrong when converting an OsString to a String lmao i have no idea how this went wrong").to_string(),
             });
         }
 
         Ok(return_instances)
-    
+    }
+
+    /// Finds an instance by folder name, name, or a unique prefix of the names. Anything only close
+    /// to a name is offered as a suggestion, and numbers aren't taken at all (they change whenever
+    /// an instance is added), since commands like `sync` delete files.
+    pub fn find_instance(query: &str) -> Result<PolyInstance, String> {
+        let instances = PolyMC::get_instances().expect("Couldn't get PolyMC instances.");
+        let query_lower = query.to_lowercase();
+
+        let matches = |check: &dyn Fn(&PolyInstance) -> bool| -> Vec<PolyInstance> {
+            instances.iter().filter(|i| check(i)).cloned().collect()
+        };
+        let names = |found: &[PolyInstance]| {
+            found
+                .iter()
+                .map(|i| i.folder_name.clone())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        let candidates = [
+            matches(&|i| i.folder_name == query),
+            matches(&|i| i.name.to_lowercase() == query_lower),
+            matches(&|i| {
+                i.folder_name.to_lowercase().starts_with(&query_lower)
+                    || i.name.to_lowercase().starts_with(&query_lower)
+            }),
+        ];
+
+        for found in candidates {
+            match found.len() {
+                0 => continue,
+                1 => return Ok(found[0].clone()),
+                _ => return Err(format!("{} could mean any of: {}", query, names(&found))),
+            }
+        }
+
+        let close = matches(&|i| {
+            is_fuzzy_match(&query_lower, &i.name.to_lowercase())
+                || is_fuzzy_match(&query_lower, &i.folder_name.to_lowercase())
+        });
+        if close.is_empty() {
+            Err(format!("Couldn't find an instance called {}.", query))
+        } else {
+            Err(format!(
+                "Couldn't find an instance called {}. Did you mean {}?",
+                query,
+                names(&close)
+            ))
+        }
+    }
+
+    /// The other way around from `get_loader_name`.
+    pub fn get_loader_uid(loader: &str) -> Option<&'static str> {
+        match loader {
+            "fabric" => Some("net.fabricmc.fabric-loader"),
+            "quilt" => Some("org.quiltmc.quilt-loader"),
+            "forge" => Some("net.minecraftforge"),
+            "neoforge" => Some("net.neoforged"),
+            "liteloader" => Some("com.mumfrey.liteloader"),
+            _ => None,
+        }
+    }
+
+    /// Makes a new instance the way PolyMC would, and returns it. `loader` can be "vanilla", and
+    /// every other loader needs a version.
+    pub fn create_instance(
+        name: &str,
+        game_version: &str,
+        loader: &str,
+        loader_version: Option<&str>,
+    ) -> Result<PolyInstance, String> {
+        // instance.cfg is one setting per line, so a line break would sneak in settings of its own
+        if name.chars().any(char::is_control) {
+            return Err(
+                "Instance names can't have line breaks or other control characters in them."
+                    .to_string(),
+            );
+        }
+
+        let mut components = vec![serde_json::json!({
+            "important": true,
+            "uid": "net.minecraft",
+            "version": game_version,
+        })];
+
+        if loader != "vanilla" {
+            let uid = PolyMC::get_loader_uid(loader)
+                .ok_or_else(|| format!("I don't know what {} is.", loader))?;
+            let loader_version =
+                loader_version.ok_or_else(|| format!("{} needs a version.", loader))?;
+
+            // fabric and quilt both run on top of fabric's intermediary mappings
+            if loader == "fabric" || loader == "quilt" {
+                components.push(serde_json::json!({
+                    "dependencyOnly": true,
+                    "uid": "net.fabricmc.intermediary",
+                    "version": game_version,
+                }));
+            }
+            components.push(serde_json::json!({
+                "uid": uid,
+                "version": loader_version,
+            }));
+        }
+
+        // PolyMC just drops anything that can't go in a folder name, and numbers duplicates
+        let base: String = name
+            .chars()
+            .filter(|c| c.is_alphanumeric() || [' ', '-', '_', '.'].contains(c))
+            .collect();
+        let base = match base.trim().trim_start_matches('.') {
+            "" => "instance".to_string(),
+            base => base.to_string(),
+        };
+        let instances_dir = format!("{}/instances", PolyMC::get_directory());
+        let mut folder_name = base.clone();
+        let mut num = 1;
+        while Path::new(&format!("{}/{}", instances_dir, folder_name)).exists() {
+            num += 1;
+            folder_name = format!("{}{}", base, num);
+        }
 
+        let instance_dir = format!("{}/{}", instances_dir, folder_name);
+        let write = |path: String, contents: String| {
+            fs::write(&path, contents).map_err(|e| format!("Couldn't write {}: {}", path, e))
+        };
+        fs::create_dir_all(format!("{}/.minecraft", instance_dir))
+            .map_err(|e| format!("Couldn't create {}: {}", instance_dir, e))?;
+        write(
+            format!("{}/instance.cfg", instance_dir),
+            format!("InstanceType=OneSix\niconKey=default\nname={}\n", name),
+        )?;
+        write(
+            format!("{}/mmc-pack.json", instance_dir),
+            serde_json::to_string_pretty(&serde_json::json!({
+                "components": components,
+                "formatVersion": 1,
+            }))
+            .expect("Couldn't serialize mmc-pack.json"),
+        )?;
+
+        PolyMC::find_instance(&folder_name)
     }
 
     pub fn get_loader_name(uid: &str) -> Option<&str> {
@@ -138,20 +282,124 @@ impl PolyMC {
             "net.fabricmc.fabric-loader" => Some("fabric"),
             "org.quiltmc.quilt-loader" => Some("quilt"),
             "net.minecraftforge" => Some("forge"),
+            "net.neoforged" => Some("neoforge"),
+            "com.mumfrey.liteloader" => Some("liteloader"),
             _ => None,
         }
     }
 }
 
+/// Whether `query` is a typo or two away from `target`, or has all its letters in order in it.
+fn is_fuzzy_match(query: &str, target: &str) -> bool {
+    let mut target_chars = target.chars();
+    if query.chars().all(|c| target_chars.any(|t| t == c)) {
+        return true;
+    }
+
+    // levenshtein distance, one row at a time
+    let target: Vec<char> = target.chars().collect();
+    let mut previous: Vec<usize> = (0..=target.len()).collect();
+    for (i, q) in query.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, t) in target.iter().enumerate() {
+            let substitution = previous[j] + usize::from(q != *t);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[target.len()] <= 2
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PolyInstance {
+    /// The instance's position in the list, sorted by folder name. Use `folder_name` to
+    /// refer to an instance across runs.
     pub id: u32,
     pub name: String,
     pub folder_name: String,
     pub game_version: String,
     pub modloader: String,
+    /// The version of the modloader itself, if the instance has one.
+    pub modloader_version: Option<String>,
+}
+#[derive(Serialize, Debug, Clone)]
+pub struct OutdatedMod {
+    pub title: String,
+    pub project_id: String,
+    pub installed: LockfileMod,
+    pub latest: ModVersion,
+}
+
+/// What `sync` had to do to make an instance match its lockfile. Paths are relative to `.minecraft`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SyncReport {
+    pub downloaded: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
 }
+
+/// What happened when copying one instance's mods into another.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct CopyReport {
+    /// Titles of the mods that were installed, dependencies included.
+    pub installed: Vec<String>,
+    /// Titles of the mods that have no version for the new instance.
+    pub missing: Vec<String>,
+    /// Anything else that stopped a mod from being copied.
+    pub problems: Vec<String>,
+}
+
+/// Whether an instance's mods are ready for another game version.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct MigrationReport {
+    pub game_version: String,
+    /// Mods with a release for the new version.
+    pub ready: Vec<MigrationMod>,
+    /// Mods that only have betas or alphas for the new version.
+    pub only_beta: Vec<MigrationMod>,
+    pub missing: Vec<MigrationMod>,
+    /// Mods that couldn't be checked, like ones Modrinth couldn't be asked about.
+    pub problems: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MigrationMod {
+    pub title: String,
+    pub project_id: String,
+    /// The version number installed now.
+    pub installed: String,
+    /// The version number that'd be picked for the new game version.
+    pub available: Option<String>,
+    /// Whether this would stop `update` from working after the migration, because it's wanted
+    /// and nothing in modpm.toml's constraints fits.
+    pub blocking: bool,
+}
+
+impl MigrationReport {
+    pub fn can_migrate(&self) -> bool {
+        self.problems.is_empty()
+            && self
+                .ready
+                .iter()
+                .chain(&self.only_beta)
+                .chain(&self.missing)
+                .all(|m| !m.blocking)
+    }
+}
+
+/// How an instance's files compare to its lockfile. Paths are relative to `.minecraft`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub ok: Vec<String>,
+    pub missing: Vec<String>,
+    /// In the lockfile, but the file's hash is different.
+    pub modified: Vec<String>,
+    pub untracked: Vec<String>,
+    /// Files that were downloaded again with `--repair`, relative to `.minecraft` like the rest.
+    pub repaired: Vec<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct PolyInstanceDataComponent {
     pub uid: String,
@@ -163,25 +411,847 @@ pub struct PolyInstanceDataJson {
 }
 
 impl PolyInstance {
-    pub async fn update(&self) -> Result<(), Box<dyn Error>> {
-        let lockfile = ModpmLockfile::get_lockfile(self.clone());
+    pub fn minecraft_directory(&self) -> String {
+        format!(
+            "{}/instances/{}/.minecraft",
+            PolyMC::get_directory(),
+            self.folder_name
+        )
+    }
+
+    /// Where a kind of project gets installed, relative to the `.minecraft` folder.
+    pub fn content_directory(project_type: &str, world: Option<&str>) -> String {
+        match project_type {
+            "resourcepack" => "resourcepacks".to_string(),
+            "shader" => "shaderpacks".to_string(),
+            "datapack" => format!(
+                "saves/{}/datapacks",
+                world.expect("Data packs need a world to go in")
+            ),
+            _ => "mods".to_string(),
+        }
+    }
+
+    pub fn get_worlds(&self) -> Vec<String> {
+        let mut worlds = vec![];
+        if let Ok(saves) = fs::read_dir(format!("{}/saves", self.minecraft_directory())) {
+            for save in saves.flatten() {
+                if save.path().is_dir() {
+                    worlds.push(save.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        worlds.sort();
+        worlds
+    }
+
+    /// Every mod in the lockfile that has a newer compatible version than the one installed.
+    pub async fn outdated(&self, client: &ModrinthClient) -> Vec<OutdatedMod> {
+        let mut outdated = vec![];
+
+        for installed in ModpmLockfile::get_lockfile(self.clone()).unwrap_or_else(|e| crash(e)) {
+            let fetched_mod = MpmMod::new(client, &installed.project_id[..])
+                .await
+                .expect("Couldn't fetch a mod");
+
+            if let Some(latest) = fetched_mod.latest_compatible_version(self) {
+                if latest.id != installed.version_id && latest.time() > installed.time() {
+                    outdated.push(OutdatedMod {
+                        title: fetched_mod.title.clone(),
+                        project_id: fetched_mod.id.clone(),
+                        installed,
+                        latest,
+                    });
+                }
+            }
+        }
+
+        outdated
+    }
+
+    /// Checks every file in the lockfile is there and hasn't been changed, and finds files modpm
+    /// doesn't know about. With `repair`, missing and changed files get downloaded again.
+    pub async fn verify(&self, repair: bool) -> VerifyReport {
+        ModpmLockfile::with_instance_locked(self, async {
+            let lockfile = ModpmLockfile::get_lockfile(self.clone()).unwrap_or_else(|e| crash(e));
+            let mut report = VerifyReport {
+                untracked: self.untracked_files(&lockfile),
+                ..Default::default()
+            };
+
+            for installed in &lockfile {
+                let relative_path = installed.relative_path();
+                let path = format!("{}/{}", self.minecraft_directory(), relative_path);
+
+                if !Path::new(&path).exists() {
+                    report.missing.push(relative_path);
+                } else if hash_file(&path).ok().as_ref() != Some(&installed.hashes.sha512) {
+                    report.modified.push(relative_path);
+                } else {
+                    report.ok.push(relative_path);
+                    continue;
+                }
+
+                if repair {
+                    self.restore_file(installed).await;
+                    report.repaired.push(installed.relative_path());
+                }
+            }
+
+            report
+        })
+        .await
+    }
+
+    /// Makes the instance's files exactly match its lockfile: anything missing or different gets
+    /// downloaded again, and files modpm installed that aren't in the lockfile any more are deleted.
+    /// Files modpm never touched are left alone, unless `remove_untracked` is set.
+    pub async fn sync(&self, remove_untracked: bool) -> SyncReport {
+        ModpmLockfile::with_instance_locked(self, async {
+            let lockfile = ModpmLockfile::get_lockfile(self.clone()).unwrap_or_else(|e| crash(e));
+            let minecraft_directory = self.minecraft_directory();
+            let mut report = SyncReport::default();
+
+            let wanted: Vec<String> = lockfile.iter().map(|m| m.relative_path()).collect();
+
+            for (installed, relative_path) in lockfile.iter().zip(&wanted) {
+                let path = format!("{}/{}", minecraft_directory, relative_path);
+
+                if hash_file(&path).ok().as_ref() == Some(&installed.hashes.sha512) {
+                    report.unchanged.push(relative_path.clone());
+                    continue;
+                }
+
+                self.restore_file(installed).await;
+                report.downloaded.push(relative_path.clone());
+            }
+
+            let mut to_remove: Vec<String> = ModpmLockfile::get_installed_files(self)
+                .into_iter()
+                .filter(|f| !wanted.contains(f))
+                .collect();
+
+            if remove_untracked {
+                for relative_path in self.untracked_files(&lockfile) {
+                    if !to_remove.contains(&relative_path) {
+                        to_remove.push(relative_path);
+                    }
+                }
+            }
+
+            for relative_path in to_remove {
+                let path = format!("{}/{}", minecraft_directory, relative_path);
+                if fs::remove_file(&path).is_ok() {
+                    report.removed.push(relative_path);
+                }
+            }
+
+            ModpmLockfile::set_installed_files(self, &wanted);
+
+            report
+        })
+        .await
+    }
+
+    /// Downloads a file from the lockfile again, making sure it has the hash the lockfile says.
+    async fn restore_file(&self, installed: &LockfileMod) {
+        let directory = format!("{}/{}", self.minecraft_directory(), installed.directory);
+        fs::create_dir_all(&directory).expect("Couldn't create the folder to download into");
+
+        eprintln!("Downloading {}", installed.filename);
+        download_file(
+            installed.url.clone(),
+            directory.clone(),
+            installed.filename.clone(),
+            &installed.hashes.sha512,
+        )
+        .await
+        .unwrap_or_else(|e| crash(format!("Failed to download {}: {}", installed.filename, e)));
+
+        // downloads always come in enabled
+        let mut path = format!("{}/{}", directory, installed.filename);
+        if installed.disabled {
+            let disabled_path = format!("{}.disabled", path);
+            fs::rename(&path, &disabled_path).ok();
+            path = disabled_path;
+        }
+        if hash_file(&path).ok().as_ref() != Some(&installed.hashes.sha512) {
+            fs::remove_file(&path).ok();
+            crash(format!(
+                "{} doesn't match the hash in the lockfile, so I got rid of it.",
+                installed.filename
+            ));
+        }
+    }
+
+    /// Files in the folders modpm installs into that aren't in the lockfile, relative to `.minecraft`.
+    fn untracked_files(&self, lockfile: &[LockfileMod]) -> Vec<String> {
+        let mut directories: Vec<&str> = lockfile.iter().map(|m| &m.directory[..]).collect();
+        directories.push("mods");
+        directories.sort();
+        directories.dedup();
+
+        let mut untracked = vec![];
+        for directory in directories {
+            let entries =
+                match fs::read_dir(format!("{}/{}", self.minecraft_directory(), directory)) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                // modpm's own files start with a dot, and so do plenty of other hidden things
+                if name.starts_with('.') || !entry.path().is_file() {
+                    continue;
+                }
+                let relative_path = format!("{}/{}", directory, name);
+                if !lockfile.iter().any(|m| m.relative_path() == relative_path) {
+                    untracked.push(relative_path);
+                }
+            }
+        }
 
-        // let mut fetched = 0;
+        untracked.sort();
+        untracked
+    }
+
+    /// Installs the mods someone asked for in `from` into this instance, picking versions for this
+    /// instance's game version and modloader. Dependencies get worked out again from scratch.
+    pub async fn copy_mods_from(&self, client: &ModrinthClient, from: &PolyInstance) -> CopyReport {
+        ModpmLockfile::with_instance_locked(self, async {
+            let from_lockfile =
+                ModpmLockfile::get_lockfile(from.clone()).unwrap_or_else(|e| crash(e));
+            let from_manifest =
+                Manifest::load(from).unwrap_or_else(|| Manifest::from_lockfile(from));
+            let mut report = CopyReport::default();
+
+            // optional dependencies that were in the old instance come along too
+            let keep: Vec<String> = from_lockfile.iter().map(|m| m.project_id.clone()).collect();
+            let mut installed_ids: Vec<String> = vec![];
+            let mut titles = HashMap::new();
+
+            for requested in from_lockfile.iter().filter(|m| m.dependency_of().is_none()) {
+                let mpm_mod = match MpmMod::new(client, &requested.project_id).await {
+                    Ok(mpm_mod) => mpm_mod,
+                    Err(_) => {
+                        report
+                            .problems
+                            .push(format!("{} isn't on Modrinth any more", requested.filename));
+                        continue;
+                    }
+                };
+
+                let mut entry = from_manifest
+                    .mods
+                    .get(&mpm_mod.slug)
+                    .or_else(|| from_manifest.mods.get(&mpm_mod.id))
+                    .cloned()
+                    .unwrap_or_default();
+                // a pinned version was for the old instance, so it doesn't mean anything here
+                entry.version = None;
+
+                if mpm_mod.project_type == "datapack" {
+                    match &entry.world {
+                        Some(world) if self.get_worlds().contains(world) => {}
+                        _ => {
+                            report.problems.push(format!(
+                            "{} is a data pack, and this instance doesn't have the world it was in",
+                            mpm_mod.title
+                        ));
+                            continue;
+                        }
+                    }
+                }
+
+                let version = match entry.pick(&mpm_mod, self) {
+                    Some(version) => version,
+                    None => {
+                        report.missing.push(mpm_mod.title.clone());
+                        continue;
+                    }
+                };
+
+                let resolution = match Resolver::new(client, self, OptionalDependencies::Skip)
+                    .keep_optional(keep.clone())
+                    .resolve(&mpm_mod.title, version)
+                    .await
+                {
+                    Ok(resolution) => resolution,
+                    Err(conflicts) => {
+                        for conflict in conflicts {
+                            report
+                                .problems
+                                .push(format!("{}: {}", mpm_mod.title, conflict));
+                        }
+                        continue;
+                    }
+                };
+
+                let installed =
+                    ModpmLockfile::get_lockfile(self.clone()).unwrap_or_else(|e| crash(e));
+                for version in resolution.versions {
+                    if installed.iter().any(|m| m.version_id == version.id) {
+                        continue;
+                    }
+
+                    let (project_type, directory) = if version.project_id == mpm_mod.id {
+                        (
+                            mpm_mod.project_type.clone(),
+                            PolyInstance::content_directory(
+                                &mpm_mod.project_type,
+                                entry.world.as_deref(),
+                            ),
+                        )
+                    } else {
+                        ("mod".to_string(), "mods".to_string())
+                    };
+                    let project_id = version.project_id.clone();
+
+                    if project_id == mpm_mod.id {
+                        titles.insert(project_id.clone(), mpm_mod.title.clone());
+                    }
+                    if let Err(e) = MpmMod::download_specific_version(
+                        version,
+                        self,
+                        &project_type,
+                        &directory,
+                        resolution.parents.get(&project_id).cloned(),
+                    )
+                    .await
+                    {
+                        report.problems.push(e);
+                        continue;
+                    }
+                    installed_ids.push(project_id.clone());
+
+                    // anything that was turned off in the old instance stays off
+                    if from_lockfile
+                        .iter()
+                        .any(|m| m.project_id == project_id && m.disabled)
+                    {
+                        if let Err(e) = ModpmLockfile::set_disabled(self, &project_id, true) {
+                            report.problems.push(e);
+                        }
+                    }
+                }
+
+                Manifest::add(self, &mpm_mod, entry);
+            }
+
+            // dependencies only came with a version, so their titles need looking up
+            let unknown: Vec<String> = installed_ids
+                .iter()
+                .filter(|id| !titles.contains_key(*id))
+                .cloned()
+                .collect();
+            titles.extend(MpmMod::get_titles(client, &unknown).await);
+            report.installed = installed_ids
+                .iter()
+                .map(|id| titles.get(id).cloned().unwrap_or_else(|| id.clone()))
+                .collect();
+
+            report
+        })
+        .await
+    }
+
+    /// Checks every mod in the lockfile for versions that work on `game_version` with this
+    /// instance's modloader.
+    pub async fn migration_check(
+        &self,
+        client: &ModrinthClient,
+        game_version: &str,
+    ) -> MigrationReport {
+        let target = PolyInstance {
+            game_version: game_version.to_string(),
+            ..self.clone()
+        };
+        let lockfile = ModpmLockfile::get_lockfile(self.clone()).unwrap_or_else(|e| crash(e));
+        let manifest = Manifest::load(self).unwrap_or_else(|| Manifest::from_lockfile(self));
+        let mut report = MigrationReport {
+            game_version: game_version.to_string(),
+            ..Default::default()
+        };
+
+        for installed in &lockfile {
+            let mpm_mod = match MpmMod::new(client, &installed.project_id).await {
+                Ok(mpm_mod) => mpm_mod,
+                Err(e) => {
+                    report.problems.push(format!(
+                        "Couldn't check {} on Modrinth: {}",
+                        installed.filename, e
+                    ));
+                    continue;
+                }
+            };
+
+            let compatible = mpm_mod.compatible_versions(&target);
+            let release = compatible
+                .iter()
+                .filter(|v| v.version_type == "release")
+                .max_by_key(|v| v.time());
+            let newest = compatible.iter().max_by_key(|v| v.time());
+
+            let blocking = match installed.dependency_of() {
+                // anything asked for has to fit what modpm.toml says about it
+                None => {
+                    let entry = manifest
+                        .mods
+                        .get(&mpm_mod.slug)
+                        .or_else(|| manifest.mods.get(&mpm_mod.id))
+                        .cloned()
+                        .unwrap_or_default();
+                    !entry.optional
+                        && entry.wanted_on_client()
+                        && entry.pick(&mpm_mod, &target).is_none()
+                }
+                // dependencies only matter if something can't do without them
+                Some(_) => {
+                    compatible.is_empty()
+                        && lockfile.iter().any(|m| {
+                            m.dependencies.iter().any(|d| {
+                                d.dependency_type == "required"
+                                    && (d.project_id.as_ref() == Some(&installed.project_id)
+                                        || d.version_id.as_ref() == Some(&installed.version_id))
+                            })
+                        })
+                }
+            };
+
+            let migration_mod = MigrationMod {
+                title: mpm_mod.title.clone(),
+                project_id: mpm_mod.id.clone(),
+                installed: installed.version_number.clone(),
+                available: release.or(newest).map(|v| v.version_number.clone()),
+                blocking,
+            };
+            match (release, newest) {
+                (Some(_), _) => report.ready.push(migration_mod),
+                (None, Some(_)) => report.only_beta.push(migration_mod),
+                (None, None) => report.missing.push(migration_mod),
+            }
+        }
+
+        report
+    }
+
+    /// Moves the instance to another game version and updates its mods to match. If the update
+    /// can't be done, even partway through, `mmc-pack.json`, the lockfile and the mods are all put
+    /// back the way they were.
+    pub async fn migrate(
+        &self,
+        client: &ModrinthClient,
+        game_version: &str,
+    ) -> Result<PolyInstance, String> {
+        ModpmLockfile::with_instance_locked(self, async {
+            let path = self.mmc_pack_path();
+            let mmc_pack =
+                fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+            let mods = ModsBackup::take(self)?;
+
+            let migrated = match self.set_game_version(game_version) {
+                Ok(migrated) => migrated
+                    .update(client)
+                    .await
+                    .map(|_| migrated)
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+
+            match migrated {
+                Ok(migrated) => {
+                    mods.discard();
+                    Ok(migrated)
+                }
+                Err(e) => {
+                    fs::write(&path, mmc_pack)
+                        .map_err(|e| format!("Couldn't put {} back: {}", path, e))?;
+                    mods.restore(self)?;
+                    Err(format!(
+                        "{}\nThe instance is still on {}.",
+                        e, self.game_version
+                    ))
+                }
+            }
+        })
+        .await
+    }
+
+    fn mmc_pack_path(&self) -> String {
+        format!("{}/mmc-pack.json", self.instance_directory())
+    }
+
+    fn instance_directory(&self) -> String {
+        format!("{}/instances/{}", PolyMC::get_directory(), self.folder_name)
+    }
+
+    /// Deletes the whole instance from PolyMC, for when making it didn't work out.
+    pub fn delete(&self) -> Result<(), String> {
+        let directory = self.instance_directory();
+        fs::remove_dir_all(&directory).map_err(|e| format!("Couldn't delete {}: {}", directory, e))
+    }
+
+    /// Changes the game version in the instance's `mmc-pack.json`, the same way PolyMC would.
+    /// Mods aren't touched, so run `update` afterwards.
+    pub fn set_game_version(&self, game_version: &str) -> Result<PolyInstance, String> {
+        // forge builds only work on one game version, so PolyMC has to pick a new one
+        if self.modloader == "forge" || self.modloader == "neoforge" {
+            return Err(format!(
+                "{} versions only work with one version of Minecraft, so change it in PolyMC instead and then run modpm update.",
+                self.modloader
+            ));
+        }
+
+        let path = self.mmc_pack_path();
+        let mut mmc_pack: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {}", path, e))?,
+        )
+        .map_err(|e| format!("Couldn't parse {}: {}", path, e))?;
+
+        let components = mmc_pack["components"]
+            .as_array_mut()
+            .ok_or_else(|| format!("{} doesn't have any components", path))?;
+        for component in components {
+            // intermediary mappings (and quilt's hashed ones) are made per game version too
+            let uid = component["uid"].as_str().unwrap_or_default();
+            if matches!(
+                uid,
+                "net.minecraft" | "net.fabricmc.intermediary" | "org.quiltmc.hashed"
+            ) {
+                component["version"] = game_version.into();
+                if component.get("cachedVersion").is_some() {
+                    component["cachedVersion"] = game_version.into();
+                }
+            }
+
+            if let Some(requires) = component
+                .get_mut("cachedRequires")
+                .and_then(|r| r.as_array_mut())
+            {
+                for requirement in requires {
+                    if requirement["uid"] == "net.minecraft" && requirement.get("equals").is_some()
+                    {
+                        requirement["equals"] = game_version.into();
+                    }
+                }
+            }
+        }
+
+        fs::write(
+            &path,
+            serde_json::to_string_pretty(&mmc_pack).expect("Couldn't serialize mmc-pack.json"),
+        )
+        .map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+
+        PolyMC::find_instance(&self.folder_name)
+    }
+
+    /// Disables or enables an installed mod, warning about anything that'll be missing a dependency.
+    pub fn set_mod_enabled(&self, query: &str, enabled: bool) -> Result<LockfileMod, String> {
+        let target = ModpmLockfile::find_mod(self, query)?;
+        let lockfile = ModpmLockfile::get_lockfile(self.clone())?;
+
+        let needs = |m: &LockfileMod, other: &LockfileMod| {
+            m.dependencies.iter().any(|d| {
+                d.dependency_type == "required"
+                    && (d.project_id.as_ref() == Some(&other.project_id)
+                        || d.version_id.as_ref() == Some(&other.version_id))
+            })
+        };
+
+        let broken: Vec<&LockfileMod> = if enabled {
+            // turning a mod back on doesn't help if what it needs is still off
+            lockfile
+                .iter()
+                .filter(|m| m.disabled && needs(&target, m))
+                .collect()
+        } else {
+            lockfile
+                .iter()
+                .filter(|m| !m.disabled && needs(m, &target))
+                .collect()
+        };
+
+        if !broken.is_empty() {
+            eprintln!(
+                "{} {} {}: {}",
+                ansi_term::Color::Yellow.paint("Warning:"),
+                target.filename,
+                if enabled {
+                    "needs mods that are disabled"
+                } else {
+                    "is needed by"
+                },
+                broken
+                    .iter()
+                    .map(|m| m.filename.clone())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+
+        ModpmLockfile::set_disabled(self, &target.project_id, !enabled)
+    }
+
+    /// Works out the instance's mods again from its manifest, against what's on Modrinth now, and
+    /// installs the result. Instances that only have a lockfile get a manifest made from it first.
+    /// If it can't work out what to install, it says why before touching anything.
+    pub async fn update(&self, client: &ModrinthClient) -> Result<(), Box<dyn Error>> {
+        ModpmLockfile::with_instance_locked(self, async {
+            let manifest = match Manifest::load(self) {
+                Some(manifest) => manifest,
+                None => {
+                    let manifest = Manifest::from_lockfile(self);
+                    manifest.save(self);
+                    eprintln!(
+                        "{}",
+                        ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                            "Made {} from the mods that are installed",
+                            Manifest::get_path(self)
+                        ))
+                    );
+                    manifest
+                }
+            };
+            let lockfile = ModpmLockfile::get_lockfile(self.clone())?;
+
+            let mut roots = vec![];
+            // project id -> (project type, directory)
+            let mut root_directories: HashMap<String, (String, String)> = HashMap::new();
+            for (name, entry) in &manifest.mods {
+                if !entry.wanted_on_client() {
+                    continue;
+                }
+
+                let mpm_mod = match MpmMod::new(client, name).await {
+                    Ok(mpm_mod) => mpm_mod,
+                    Err(_) if entry.optional => {
+                        eprintln!(
+                            "{}",
+                            ansi_term::Color::RGB(128, 128, 128)
+                                .paint(format!("Skipping {}, as it isn't on Modrinth", name))
+                        );
+                        continue;
+                    }
+                    Err(_) => {
+                        return Err(format!("{} in modpm.toml isn't on Modrinth.", name).into())
+                    }
+                };
+
+                let version = match entry.pick(&mpm_mod, self) {
+                    Some(version) => version,
+                    None if entry.optional => {
+                        eprintln!(
+                            "{}",
+                            ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                                "Skipping {}, as no version of it matches",
+                                mpm_mod.title
+                            ))
+                        );
+                        continue;
+                    }
+                    None => {
+                        return Err(format!(
+                            "No version of {} matches both modpm.toml and this instance.",
+                            ansi_term::Color::Green.paint(&mpm_mod.title)
+                        )
+                        .into())
+                    }
+                };
+
+                if mpm_mod.project_type == "datapack" && entry.world.is_none() {
+                    return Err(format!(
+                        "{} is a data pack, so it needs a world in modpm.toml.",
+                        ansi_term::Color::Green.paint(&mpm_mod.title)
+                    )
+                    .into());
+                }
+                root_directories.insert(
+                    mpm_mod.id.clone(),
+                    (
+                        mpm_mod.project_type.clone(),
+                        PolyInstance::content_directory(
+                            &mpm_mod.project_type,
+                            entry.world.as_deref(),
+                        ),
+                    ),
+                );
+                roots.push((mpm_mod.title.clone(), version));
+            }
+
+            // optional dependencies that were picked before get picked again
+            let keep = lockfile.iter().map(|m| m.project_id.clone()).collect();
+            let resolution = match Resolver::new(client, self, OptionalDependencies::Skip)
+                .fresh()
+                .keep_optional(keep)
+                .resolve_all(roots)
+                .await
+            {
+                Ok(resolution) => resolution,
+                Err(conflicts) => {
+                    eprintln!(
+                        "{}",
+                        ansi_term::Color::Red
+                            .paint("I couldn't find a set of mods that work together:")
+                    );
+                    for conflict in conflicts {
+                        eprintln!("  - {}", conflict);
+                    }
+                    return Err("Nothing was changed.".into());
+                }
+            };
+
+            for version in &resolution.versions {
+                let (project_type, directory) = root_directories
+                    .get(&version.project_id)
+                    .cloned()
+                    .unwrap_or(("mod".to_string(), "mods".to_string()));
+                let new_filename = version.primary_file().filename;
+
+                match lockfile.iter().find(|m| m.project_id == version.project_id) {
+                    Some(old) if old.version_id == version.id && old.directory == directory => {
+                        continue
+                    }
+                    Some(old) => eprintln!(
+                        "{} {} -> {}",
+                        ansi_term::Color::Purple.paint("Updating"),
+                        old.filename,
+                        new_filename
+                    ),
+                    None => eprintln!(
+                        "{} {}",
+                        ansi_term::Color::Green.paint("Adding"),
+                        new_filename
+                    ),
+                }
+
+                MpmMod::download_specific_version(
+                    version.clone(),
+                    self,
+                    &project_type,
+                    &directory,
+                    resolution.parents.get(&version.project_id).cloned(),
+                )
+                .await?;
+            }
+
+            for old in &lockfile {
+                if resolution
+                    .versions
+                    .iter()
+                    .any(|v| v.project_id == old.project_id)
+                {
+                    continue;
+                }
+
+                ModpmLockfile::remove_from_lockfile(self.clone(), &old.project_id);
+                fs::remove_file(format!(
+                    "{}/{}",
+                    self.minecraft_directory(),
+                    old.relative_path()
+                ))
+                .ok();
+                eprintln!(
+                    "{} {}",
+                    ansi_term::Color::Red.paint("Removing"),
+                    old.filename
+                );
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Everything `update` can change in an instance, kept aside so `migrate` can put it back if the
+/// update fails partway through.
+struct ModsBackup {
+    directory: String,
+    /// The lockfile, `.modpm_installed.json` and `modpm.toml`, or `None` where there wasn't one.
+    texts: Vec<(String, Option<String>)>,
+    /// The files in the lockfile, relative to `.minecraft`.
+    files: Vec<String>,
+}
+
+impl ModsBackup {
+    fn take(instance: &PolyInstance) -> Result<ModsBackup, String> {
+        let minecraft_directory = instance.minecraft_directory();
+        let directory = format!("{}/.modpm_backup", minecraft_directory);
+        fs::remove_dir_all(&directory).ok();
 
-        let mut new_lockfile = vec![];
+        let texts = [
+            ModpmLockfile::get_path(instance),
+            installed_files_path(instance),
+            Manifest::get_path(instance),
+        ]
+        .into_iter()
+        .map(|path| {
+            let text = fs::read_to_string(&path).ok();
+            (path, text)
+        })
+        .collect();
 
-        for mut ver in lockfile {
-            let fetched_mod = MpmMod::new(&ver.version.project_id[..]).await.expect("Couldn't fetch a mod");
-            // fetched += 1;
-            ver.mpm_mod = Some(fetched_mod);
+        let mut files = vec![];
+        for installed in ModpmLockfile::get_lockfile(instance.clone())? {
+            let relative_path = installed.relative_path();
+            let from = format!("{}/{}", minecraft_directory, relative_path);
+            if !Path::new(&from).exists() {
+                continue;
+            }
+
+            let to = format!("{}/{}", directory, relative_path);
+            if let Some(parent) = Path::new(&to).parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Couldn't create {}: {}", parent.display(), e))?;
+            }
+            // updates swap files out rather than writing into them, so a hard link is enough
+            fs::hard_link(&from, &to)
+                .or_else(|_| fs::copy(&from, &to).map(|_| ()))
+                .map_err(|e| format!("Couldn't back up {}: {}", from, e))?;
+            files.push(relative_path);
+        }
+
+        Ok(ModsBackup {
+            directory,
+            texts,
+            files,
+        })
+    }
+
+    /// Gets rid of anything the failed update added, and puts the old files back.
+    fn restore(self, instance: &PolyInstance) -> Result<(), String> {
+        let minecraft_directory = instance.minecraft_directory();
 
-            new_lockfile.push(ver);
+        for installed in ModpmLockfile::get_lockfile(instance.clone())? {
+            let relative_path = installed.relative_path();
+            if !self.files.contains(&relative_path) {
+                fs::remove_file(format!("{}/{}", minecraft_directory, relative_path)).ok();
+            }
         }
 
-        for ver in new_lockfile {
-            println!("{}", ver.mpm_mod.unwrap().title);
+        for relative_path in &self.files {
+            let to = format!("{}/{}", minecraft_directory, relative_path);
+            fs::remove_file(&to).ok();
+            fs::rename(format!("{}/{}", self.directory, relative_path), &to)
+                .map_err(|e| format!("Couldn't put {} back: {}", to, e))?;
         }
 
+        for (path, text) in &self.texts {
+            match text {
+                Some(text) => write_atomically(path, text)
+                    .map_err(|e| format!("Couldn't put {} back: {}", path, e))?,
+                None => {
+                    fs::remove_file(path).ok();
+                }
+            }
+        }
+
+        self.discard();
         Ok(())
     }
+
+    fn discard(self) {
+        fs::remove_dir_all(&self.directory).ok();
+    }
 }
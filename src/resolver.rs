@@ -0,0 +1,496 @@
+use crate::client::ModrinthClient;
+use crate::data_structs::{LockfileMod, ModpmLockfile};
+use crate::modrinth::{ModVersion, ModVersionDependencies, MpmMod};
+use crate::polymc::PolyInstance;
+use crate::{ask_user_with_default, crash};
+use async_recursion::async_recursion;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Something that stops a consistent set of mods from being installed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// A required dependency has no version for the instance's game version and modloader.
+    NoCompatibleVersion {
+        project: String,
+        required_by: String,
+    },
+    /// A mod is marked as incompatible with another mod that would end up in the instance.
+    Incompatible {
+        project: String,
+        incompatible_with: String,
+    },
+    /// Two different versions of the same project were pinned.
+    VersionMismatch {
+        project: String,
+        wanted: String,
+        existing: String,
+    },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::NoCompatibleVersion {
+                project,
+                required_by,
+            } => write!(
+                f,
+                "{} is required by {}, but has no version that supports this instance",
+                project, required_by
+            ),
+            Conflict::Incompatible {
+                project,
+                incompatible_with,
+            } => write!(f, "{} is incompatible with {}", project, incompatible_with),
+            Conflict::VersionMismatch {
+                project,
+                wanted,
+                existing,
+            } => write!(
+                f,
+                "{} is pinned to version {}, but version {} was already picked",
+                project, wanted, existing
+            ),
+        }
+    }
+}
+
+/// What to do with dependencies that are marked as optional.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptionalDependencies {
+    /// List them and let the user pick.
+    #[default]
+    Ask,
+    Install,
+    Skip,
+}
+
+/// The full set of versions to install, dependencies before their dependents.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub versions: Vec<ModVersion>,
+    /// project id -> project id of the mod that pulled it in
+    pub parents: HashMap<String, String>,
+    pub cycles: Vec<Vec<String>>,
+}
+
+/// Builds the whole dependency graph for a version before anything gets downloaded.
+pub struct Resolver<'a> {
+    client: &'a ModrinthClient,
+    instance: &'a PolyInstance,
+    optional: OptionalDependencies,
+    installed: Vec<LockfileMod>,
+    resolved: HashMap<String, ModVersion>,
+    parents: HashMap<String, String>,
+    order: Vec<ModVersion>,
+    titles: HashMap<String, String>,
+    // (project that declared it, project it's incompatible with)
+    incompatibilities: Vec<(String, String)>,
+    conflicts: Vec<Conflict>,
+    cycles: Vec<Vec<String>>,
+    // optional dependencies that get picked again without asking
+    keep: Vec<String>,
+    // the versions asked for directly, which win over whatever's latest when something depends on them
+    roots: HashMap<String, ModVersion>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(
+        client: &'a ModrinthClient,
+        instance: &'a PolyInstance,
+        optional: OptionalDependencies,
+    ) -> Resolver<'a> {
+        Resolver {
+            client,
+            instance,
+            optional,
+            installed: ModpmLockfile::get_lockfile(instance.clone()).unwrap_or_else(|e| crash(e)),
+            resolved: HashMap::new(),
+            parents: HashMap::new(),
+            order: vec![],
+            titles: HashMap::new(),
+            incompatibilities: vec![],
+            conflicts: vec![],
+            cycles: vec![],
+            keep: vec![],
+            roots: HashMap::new(),
+        }
+    }
+
+    /// Resolves from scratch instead of building on what's installed, like `update` does.
+    pub fn fresh(mut self) -> Resolver<'a> {
+        self.installed = vec![];
+        self
+    }
+
+    /// Optional dependencies of these projects get picked without asking.
+    pub fn keep_optional(mut self, keep: Vec<String>) -> Resolver<'a> {
+        self.keep = keep;
+        self
+    }
+
+    pub async fn resolve(self, title: &str, root: ModVersion) -> Result<Resolution, Vec<Conflict>> {
+        self.resolve_all(vec![(title.to_string(), root)]).await
+    }
+
+    /// Resolves several mods at once, so they all end up in one consistent set.
+    pub async fn resolve_all(
+        mut self,
+        roots: Vec<(String, ModVersion)>,
+    ) -> Result<Resolution, Vec<Conflict>> {
+        for (title, root) in &roots {
+            self.titles.insert(root.project_id.clone(), title.clone());
+            self.roots.insert(root.project_id.clone(), root.clone());
+        }
+
+        let mut stack = vec![];
+        for (_, root) in roots {
+            self.visit(root, false, None, &mut stack).await;
+        }
+
+        self.check_incompatibilities();
+
+        if !self.conflicts.is_empty() {
+            return Err(self.conflicts);
+        }
+
+        Ok(Resolution {
+            versions: self.order,
+            parents: self.parents,
+            cycles: self.cycles,
+        })
+    }
+
+    #[async_recursion]
+    async fn visit(
+        &mut self,
+        version: ModVersion,
+        pinned: bool,
+        parent: Option<String>,
+        stack: &mut Vec<String>,
+    ) {
+        let project_id = version.project_id.clone();
+
+        if let Some(position) = stack.iter().position(|p| *p == project_id) {
+            let mut cycle: Vec<String> = stack[position..].iter().map(|p| self.name(p)).collect();
+            cycle.push(self.name(&project_id));
+            self.cycles.push(cycle);
+            return;
+        }
+
+        // the root mod was picked on purpose, so only dependencies get to reuse what's installed
+        if let (Some(installed), Some(_)) = (self.installed_version(&project_id), &parent) {
+            if !pinned || installed.version_id == version.id || installed.time() >= version.time() {
+                self.note_installed(&project_id);
+                return;
+            }
+
+            eprintln!(
+                "{}",
+                ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                    "Upgrading {} from {} to {}, as a dependency needs it",
+                    self.name(&project_id),
+                    installed.version_number,
+                    version.version_number
+                ))
+            );
+        }
+
+        if let Some(existing) = self.resolved.get(&project_id) {
+            if pinned && existing.id != version.id {
+                self.conflicts.push(Conflict::VersionMismatch {
+                    project: self.name(&project_id),
+                    wanted: version.version_number.clone(),
+                    existing: existing.version_number.clone(),
+                });
+            }
+            return;
+        }
+
+        self.resolved.insert(project_id.clone(), version.clone());
+        // something asked for on its own stays that way, even if another mod needs it too
+        if let Some(parent) = parent {
+            if !self.is_requested(&project_id) {
+                self.parents.insert(project_id.clone(), parent);
+            }
+        }
+        stack.push(project_id.clone());
+
+        let mut optional_deps = vec![];
+
+        for dep in &version.dependencies {
+            match &dep.dependency_type[..] {
+                "required" => {
+                    // a dependency we already have doesn't need fetching again
+                    if let Some(dep_project) = &dep.project_id {
+                        let installed = self.installed_version(dep_project);
+                        let have_it = match &dep.version_id {
+                            None => {
+                                if installed.is_some() {
+                                    self.note_installed(dep_project);
+                                    continue;
+                                }
+                                self.resolved.contains_key(dep_project)
+                                    && !stack.contains(dep_project)
+                            }
+                            Some(version_id) => {
+                                installed.is_some_and(|v| &v.version_id == version_id)
+                            }
+                        };
+                        if have_it {
+                            continue;
+                        }
+                    }
+
+                    match self.fetch_dependency(dep).await {
+                        Some(dep_version) => {
+                            self.visit(
+                                dep_version,
+                                dep.version_id.is_some(),
+                                Some(project_id.clone()),
+                                stack,
+                            )
+                            .await
+                        }
+                        None => self.conflicts.push(Conflict::NoCompatibleVersion {
+                            project: self.name(dep.project_id.as_deref().unwrap_or("unknown")),
+                            required_by: self.name(&project_id),
+                        }),
+                    }
+                }
+                "incompatible" => {
+                    let other = match &dep.project_id {
+                        Some(other) => other.clone(),
+                        None => {
+                            ModVersion::new(
+                                self.client,
+                                dep.version_id.clone().expect(
+                                    "A dependency didn't have a version ID or a project ID",
+                                ),
+                            )
+                            .await
+                            .project_id
+                        }
+                    };
+                    self.incompatibilities.push((project_id.clone(), other));
+                }
+                "optional" => optional_deps.push(dep.clone()),
+                _ => {}
+            }
+        }
+
+        for (dep_version, pinned) in self.pick_optional(&project_id, optional_deps).await {
+            self.visit(dep_version, pinned, Some(project_id.clone()), stack)
+                .await;
+        }
+
+        stack.pop();
+        self.order.push(version);
+    }
+
+    async fn fetch_dependency(&mut self, dep: &ModVersionDependencies) -> Option<ModVersion> {
+        if let Some(version_id) = &dep.version_id {
+            return Some(ModVersion::new(self.client, version_id.clone()).await);
+        }
+        if let Some(root) = dep.project_id.as_ref().and_then(|p| self.roots.get(p)) {
+            return Some(root.clone());
+        }
+
+        eprintln!(
+            "{}",
+            ansi_term::Color::RGB(128, 128, 128)
+                .paint("No version specified, looking for latest compatible version")
+        );
+        let dep_mod = MpmMod::new(
+            self.client,
+            dep.project_id
+                .as_ref()
+                .expect("A dependency didn't have a version ID or a project ID"),
+        )
+        .await
+        .expect("Couldn't fetch a dependency's project");
+
+        self.titles
+            .insert(dep_mod.id.clone(), dep_mod.title.clone());
+
+        dep_mod.latest_compatible_version(self.instance)
+    }
+
+    /// Works out which optional dependencies of a mod should be installed alongside it.
+    async fn pick_optional(
+        &mut self,
+        project_id: &str,
+        deps: Vec<ModVersionDependencies>,
+    ) -> Vec<(ModVersion, bool)> {
+        if self.optional == OptionalDependencies::Skip && self.keep.is_empty() {
+            return vec![];
+        }
+
+        let mut candidates = vec![];
+        for dep in deps {
+            let dep_project = match &dep.project_id {
+                Some(dep_project) => dep_project.clone(),
+                None => {
+                    ModVersion::new(
+                        self.client,
+                        dep.version_id
+                            .clone()
+                            .expect("A dependency didn't have a version ID or a project ID"),
+                    )
+                    .await
+                    .project_id
+                }
+            };
+
+            let already_installed = self.installed.iter().any(|m| m.project_id == dep_project);
+            if self.resolved.contains_key(&dep_project) || already_installed {
+                continue;
+            }
+
+            let dep_mod = MpmMod::new(self.client, &dep_project)
+                .await
+                .expect("Couldn't fetch a dependency's project");
+            self.titles
+                .insert(dep_mod.id.clone(), dep_mod.title.clone());
+
+            let dep_version = match &dep.version_id {
+                Some(version_id) => Some(ModVersion::new(self.client, version_id.clone()).await),
+                None => match self.roots.get(&dep_mod.id) {
+                    Some(root) => Some(root.clone()),
+                    None => dep_mod.latest_compatible_version(self.instance),
+                },
+            };
+
+            match dep_version {
+                Some(dep_version) => candidates.push((dep_version, dep.version_id.is_some())),
+                None => eprintln!(
+                    "{}",
+                    ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                        "Skipping optional dependency {}, as it has no version that supports this instance",
+                        dep_mod.title
+                    ))
+                ),
+            }
+        }
+
+        let (mut kept, candidates): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .partition(|(v, _)| self.keep.contains(&v.project_id));
+
+        if self.optional == OptionalDependencies::Skip {
+            return kept;
+        }
+        if candidates.is_empty() || self.optional == OptionalDependencies::Install {
+            kept.extend(candidates);
+            return kept;
+        }
+
+        eprintln!(
+            "{} has optional dependencies:",
+            ansi_term::Color::Green.paint(self.name(project_id))
+        );
+        for (num, (dep_version, _)) in candidates.iter().enumerate() {
+            eprintln!(
+                "{}: {} ({})",
+                num + 1,
+                ansi_term::Color::Green.paint(self.name(&dep_version.project_id)),
+                ansi_term::Color::RGB(128, 128, 128).paint(&dep_version.version_number)
+            );
+        }
+
+        let answer = ask_user_with_default(
+            "What optional dependencies do you want to install? (e.g. \"1,3\" or \"all\", leave empty for none) ",
+            "",
+        );
+        if answer == "all" {
+            kept.extend(candidates);
+            return kept;
+        }
+
+        let picked: Vec<usize> = answer
+            .split(',')
+            .filter_map(|n| n.trim().parse::<usize>().ok())
+            .collect();
+
+        kept.extend(
+            candidates
+                .into_iter()
+                .enumerate()
+                .filter(|(num, _)| picked.contains(&(num + 1)))
+                .map(|(_, candidate)| candidate),
+        );
+        kept
+    }
+
+    fn check_incompatibilities(&mut self) {
+        let mut found = vec![];
+
+        for (declared_by, other) in &self.incompatibilities {
+            let installed = self.installed.iter().any(|m| &m.project_id == other);
+            if self.resolved.contains_key(other) || installed {
+                found.push((declared_by.clone(), other.clone()));
+            }
+        }
+
+        // mods that are already installed can also rule out the new ones
+        for installed in &self.installed {
+            for dep in &installed.dependencies {
+                if dep.dependency_type != "incompatible" {
+                    continue;
+                }
+                if let Some(other) = &dep.project_id {
+                    if self.resolved.contains_key(other) {
+                        found.push((installed.project_id.clone(), other.clone()));
+                    }
+                }
+            }
+        }
+
+        for (declared_by, other) in found {
+            let conflict = Conflict::Incompatible {
+                project: self.name(&declared_by),
+                incompatible_with: self.name(&other),
+            };
+            if !self.conflicts.contains(&conflict) {
+                self.conflicts.push(conflict);
+            }
+        }
+    }
+
+    fn is_requested(&self, project_id: &str) -> bool {
+        self.roots.contains_key(project_id)
+            || self
+                .installed
+                .iter()
+                .any(|m| m.project_id == project_id && m.dependency_of().is_none())
+    }
+
+    fn installed_version(&self, project_id: &str) -> Option<LockfileMod> {
+        self.installed
+            .iter()
+            .find(|m| m.project_id == project_id)
+            .cloned()
+    }
+
+    fn note_installed(&self, project_id: &str) {
+        eprintln!(
+            "{}",
+            ansi_term::Color::RGB(128, 128, 128).paint(format!(
+                "{} is already installed, skipping",
+                self.name(project_id)
+            ))
+        );
+    }
+
+    fn name(&self, project_id: &str) -> String {
+        if let Some(title) = self.titles.get(project_id) {
+            return title.clone();
+        }
+
+        match self.installed.iter().find(|m| m.project_id == project_id) {
+            Some(installed) => installed.filename.clone(),
+            None => project_id.to_string(),
+        }
+    }
+}
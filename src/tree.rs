@@ -0,0 +1,297 @@
+use crate::client::ModrinthClient;
+use crate::crash;
+use crate::data_structs::ModpmLockfile;
+use crate::modrinth::{ModVersion, MpmMod};
+use crate::polymc::PolyInstance;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+struct TreeNode {
+    title: String,
+    version_number: Option<String>,
+    // (project id, dependency type)
+    edges: Vec<(String, String)>,
+}
+
+/// A dependency graph that can be printed from the top down, or from a mod back up to whatever needs it.
+pub struct DependencyTree {
+    nodes: HashMap<String, TreeNode>,
+    pub roots: Vec<String>,
+}
+
+impl DependencyTree {
+    /// Builds the graph for a Modrinth project, as it would be installed into an instance.
+    pub async fn for_mod(
+        client: &ModrinthClient,
+        mpm_mod: &MpmMod,
+        instance: &PolyInstance,
+    ) -> Option<DependencyTree> {
+        let root = mpm_mod.latest_compatible_version(instance)?;
+
+        let mut tree = DependencyTree {
+            nodes: HashMap::new(),
+            roots: vec![mpm_mod.id.clone()],
+        };
+        let mut titles = HashMap::new();
+        titles.insert(mpm_mod.id.clone(), mpm_mod.title.clone());
+
+        let mut queue = vec![root];
+        while let Some(version) = queue.pop() {
+            if tree.nodes.contains_key(&version.project_id) {
+                continue;
+            }
+
+            let mut edges = vec![];
+            for dep in &version.dependencies {
+                let dep_project = dep.resolve_project(client).await;
+
+                let expand =
+                    dep.dependency_type != "incompatible" && !tree.nodes.contains_key(&dep_project);
+                if expand {
+                    let dep_version = match &dep.version_id {
+                        Some(version_id) => Some(ModVersion::new(client, version_id.clone()).await),
+                        None => match MpmMod::new(client, &dep_project).await {
+                            Ok(dep_mod) => {
+                                titles.insert(dep_mod.id.clone(), dep_mod.title.clone());
+                                dep_mod.latest_compatible_version(instance)
+                            }
+                            Err(_) => None,
+                        },
+                    };
+                    if let Some(dep_version) = dep_version {
+                        queue.push(dep_version);
+                    }
+                }
+
+                edges.push((dep_project, dep.dependency_type.clone()));
+            }
+
+            tree.nodes.insert(
+                version.project_id.clone(),
+                TreeNode {
+                    title: String::new(),
+                    version_number: Some(version.version_number.clone()),
+                    edges,
+                },
+            );
+        }
+
+        tree.fill_titles(client, titles).await;
+
+        Some(tree)
+    }
+
+    /// Builds the graph for every mod in an instance's lockfile.
+    pub async fn for_instance(client: &ModrinthClient, instance: &PolyInstance) -> DependencyTree {
+        let lockfile = ModpmLockfile::get_lockfile(instance.clone()).unwrap_or_else(|e| crash(e));
+
+        let mut tree = DependencyTree {
+            nodes: HashMap::new(),
+            roots: vec![],
+        };
+
+        for installed in &lockfile {
+            let mut edges = vec![];
+            for dep in &installed.dependencies {
+                // deps pinned to an installed version don't need a request to find their project
+                let dep_project = match lockfile
+                    .iter()
+                    .find(|m| Some(&m.version_id) == dep.version_id.as_ref())
+                {
+                    Some(m) => m.project_id.clone(),
+                    None => dep.resolve_project(client).await,
+                };
+                edges.push((dep_project, dep.dependency_type.clone()));
+            }
+
+            tree.nodes.insert(
+                installed.project_id.clone(),
+                TreeNode {
+                    title: String::new(),
+                    version_number: Some(installed.version_number.clone()),
+                    edges,
+                },
+            );
+        }
+
+        // roots are whatever was asked for, even if something else needs it too
+        tree.roots = lockfile
+            .iter()
+            .filter(|m| m.dependency_of().is_none())
+            .map(|m| m.project_id.clone())
+            .collect();
+
+        tree.fill_titles(client, HashMap::new()).await;
+
+        tree
+    }
+
+    /// Finds a project in the graph by id or (case-insensitive) title.
+    pub fn find(&self, query: &str) -> Option<String> {
+        if self.nodes.contains_key(query) {
+            return Some(query.to_string());
+        }
+
+        self.nodes
+            .iter()
+            .find(|(_, node)| node.title.to_lowercase() == query.to_lowercase())
+            .map(|(id, _)| id.clone())
+    }
+
+    pub fn print(&self) {
+        crate::say!("{}", self.render().trim_end());
+    }
+
+    /// Prints everything that depends on a project, all the way up.
+    pub fn print_reverse(&self, project_id: &str) {
+        crate::say!("{}", self.render_reverse(project_id).trim_end());
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for root in &self.roots {
+            writeln!(out, "{}", self.label(root, None)).unwrap();
+            self.render_children(&mut out, root, "", &mut vec![root.clone()], false);
+        }
+        out
+    }
+
+    pub fn render_reverse(&self, project_id: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", self.label(project_id, None)).unwrap();
+        self.render_children(
+            &mut out,
+            project_id,
+            "",
+            &mut vec![project_id.to_string()],
+            true,
+        );
+        out
+    }
+
+    fn render_children(
+        &self,
+        out: &mut String,
+        project_id: &str,
+        prefix: &str,
+        path: &mut Vec<String>,
+        reverse: bool,
+    ) {
+        let children: Vec<(String, String)> = if reverse {
+            let mut dependents: Vec<(String, String)> = self
+                .nodes
+                .iter()
+                .flat_map(|(id, node)| {
+                    node.edges
+                        .iter()
+                        .filter(|(p, _)| p == project_id)
+                        .map(move |(_, t)| (id.clone(), t.clone()))
+                })
+                .collect();
+            dependents.sort();
+            dependents
+        } else {
+            match self.nodes.get(project_id) {
+                Some(node) => node.edges.clone(),
+                None => vec![],
+            }
+        };
+
+        for (num, (child, dependency_type)) in children.iter().enumerate() {
+            let last = num == children.len() - 1;
+            let seen = path.contains(child);
+
+            writeln!(
+                out,
+                "{}{} {}{}",
+                prefix,
+                if last { "└" } else { "├" },
+                self.label(child, Some(dependency_type)),
+                if seen {
+                    ansi_term::Color::RGB(128, 128, 128)
+                        .paint(" (*)")
+                        .to_string()
+                } else {
+                    String::new()
+                }
+            )
+            .unwrap();
+
+            // incompatible mods aren't pulled in by anything, so there's nothing to follow
+            if seen || dependency_type == "incompatible" {
+                continue;
+            }
+
+            path.push(child.clone());
+            let new_prefix = format!("{}{}", prefix, if last { "  " } else { "│ " });
+            self.render_children(out, child, &new_prefix, path, reverse);
+            path.pop();
+        }
+    }
+
+    fn label(&self, project_id: &str, dependency_type: Option<&str>) -> String {
+        let (title, version_number) = match self.nodes.get(project_id) {
+            Some(node) if !node.title.is_empty() => {
+                (node.title.clone(), node.version_number.clone())
+            }
+            Some(node) => (project_id.to_string(), node.version_number.clone()),
+            None => (project_id.to_string(), None),
+        };
+
+        let kind = match dependency_type {
+            Some("required") | None => String::new(),
+            Some("optional") => ansi_term::Color::Yellow.paint(" [optional]").to_string(),
+            Some("embedded") => ansi_term::Color::Blue.paint(" [embedded]").to_string(),
+            Some("incompatible") => ansi_term::Color::Red.paint(" [incompatible]").to_string(),
+            Some(other) => format!(" [{}]", other),
+        };
+
+        format!(
+            "{}{}{}",
+            ansi_term::Color::Green.paint(title),
+            match version_number {
+                Some(version_number) => ansi_term::Color::RGB(128, 128, 128)
+                    .paint(format!(" ({})", version_number))
+                    .to_string(),
+                None => String::new(),
+            },
+            kind
+        )
+    }
+
+    async fn fill_titles(&mut self, client: &ModrinthClient, mut titles: HashMap<String, String>) {
+        let mut missing: Vec<String> = vec![];
+        for (id, node) in &self.nodes {
+            if node.title.is_empty() && !titles.contains_key(id) {
+                missing.push(id.clone());
+            }
+            for (p, _) in &node.edges {
+                if !self.nodes.contains_key(p) && !titles.contains_key(p) && !missing.contains(p) {
+                    missing.push(p.clone());
+                }
+            }
+        }
+
+        titles.extend(MpmMod::get_titles(client, &missing).await);
+
+        for (id, title) in titles {
+            match self.nodes.get_mut(&id) {
+                Some(node) => {
+                    if node.title.is_empty() {
+                        node.title = title;
+                    }
+                }
+                None => {
+                    self.nodes.insert(
+                        id,
+                        TreeNode {
+                            title,
+                            version_number: None,
+                            edges: vec![],
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! A stand-in for the Modrinth API, serving the fixtures in `tests/fixtures` over plain HTTP.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{env, fs, thread};
+
+use modpm::client::ModrinthClient;
+use modpm::polymc::{PolyInstance, PolyMC};
+
+/// Tests point `$HOME` at their own fake PolyMC folder, so they can't run at the same time.
+static HOME_LOCK: Mutex<()> = Mutex::new(());
+static NEXT_HOME: AtomicUsize = AtomicUsize::new(0);
+
+pub struct MockModrinth {
+    pub base: String,
+}
+
+impl MockModrinth {
+    pub fn start() -> MockModrinth {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind the mock server");
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let fixtures: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(fixture_path("modrinth.json"))
+                .unwrap()
+                .replace("{{BASE}}", &base),
+        )
+        .unwrap();
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let fixtures = fixtures.clone();
+                let hits = hits.clone();
+                thread::spawn(move || handle(stream, &fixtures, &hits));
+            }
+        });
+
+        MockModrinth { base }
+    }
+
+    /// A client for the mock server. It never reads cached API responses, so tests always see the
+    /// fixtures.
+    pub fn client(&self) -> ModrinthClient {
+        ModrinthClient {
+            cache_ttl: None,
+            ..ModrinthClient::new(&format!("{}/v2", self.base))
+        }
+    }
+}
+
+/// A throwaway `$HOME` with a PolyMC folder holding one instance.
+pub struct FakeHome {
+    pub path: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl FakeHome {
+    pub fn new(modloader_uid: &str, game_version: &str) -> FakeHome {
+        let guard = HOME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let path = env::temp_dir().join(format!(
+            "modpm-test-{}-{}",
+            std::process::id(),
+            NEXT_HOME.fetch_add(1, Ordering::Relaxed)
+        ));
+        env::set_var("HOME", &path);
+        env::set_var("MODPM_CACHE_DIR", path.join(".cache/modpm"));
+
+        let home = FakeHome {
+            path,
+            _guard: guard,
+        };
+        home.add_instance("test", "Test Instance", modloader_uid, game_version);
+        home
+    }
+
+    /// Adds another instance next to the first one.
+    pub fn add_instance(
+        &self,
+        folder: &str,
+        name: &str,
+        modloader_uid: &str,
+        game_version: &str,
+    ) -> PolyInstance {
+        let instance_dir = self.path.join(".local/share/PolyMC/instances").join(folder);
+        fs::create_dir_all(instance_dir.join(".minecraft/mods")).unwrap();
+        fs::write(
+            instance_dir.join("instance.cfg"),
+            format!("name={}\n", name),
+        )
+        .unwrap();
+        fs::write(
+            instance_dir.join("mmc-pack.json"),
+            format!(
+                r#"{{"components":[{{"uid":"net.minecraft","version":"{}"}},{{"uid":"{}","version":"0.14.21"}}]}}"#,
+                game_version, modloader_uid
+            ),
+        )
+        .unwrap();
+
+        PolyMC::find_instance(folder).unwrap()
+    }
+
+    /// Runs the real modpm binary against the mock server, in this home. For things that exit.
+    pub fn modpm(&self, server: &MockModrinth, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_modpm"))
+            .args(args)
+            .env("HOME", &self.path)
+            .env("XDG_CONFIG_HOME", self.path.join(".config"))
+            .env("MODPM_CACHE_DIR", self.path.join(".cache/modpm"))
+            .env("MODPM_API_BASE", format!("{}/v2", server.base))
+            .env("MODPM_META_BASE", format!("{}/v1", server.base))
+            .stdin(Stdio::null())
+            .output()
+            .expect("Couldn't run modpm")
+    }
+
+    pub fn instance(&self) -> PolyInstance {
+        PolyMC::find_instance("test").unwrap()
+    }
+
+    pub fn mods_dir(&self) -> PathBuf {
+        self.path
+            .join(".local/share/PolyMC/instances/test/.minecraft/mods")
+    }
+}
+
+impl Drop for FakeHome {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.path).ok();
+    }
+}
+
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn handle(mut stream: TcpStream, fixtures: &serde_json::Value, hits: &AtomicUsize) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+            break;
+        }
+    }
+
+    let target = percent_decode(request_line.split(' ').nth(1).unwrap_or("/"));
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target.clone(), String::new()),
+    };
+
+    // `/chunked/...` is sent without a Content-Length, like some download hosts do
+    if let Some(rest) = path.strip_prefix("/chunked") {
+        let (status, body) = route(rest, &query, fixtures);
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n",
+            status,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+        write!(stream, "\r\n0\r\n\r\n").unwrap();
+        return;
+    }
+
+    // `/v2/flaky/...` is rate limited on every other request
+    let (status, body, extra_headers) = match path.strip_prefix("/v2/flaky") {
+        Some(_) if hits.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) => (
+            "429 Too Many Requests",
+            b"{}".to_vec(),
+            "Retry-After: 0\r\n",
+        ),
+        Some(rest) => {
+            let (status, body) = route(&format!("/v2{}", rest), &query, fixtures);
+            (status, body, "")
+        }
+        None => {
+            let (status, body) = route(&path, &query, fixtures);
+            (status, body, "")
+        }
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+        status,
+        body.len(),
+        extra_headers
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+fn route(path: &str, query: &str, fixtures: &serde_json::Value) -> (&'static str, Vec<u8>) {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let projects = fixtures["projects"].as_array().unwrap();
+    let versions = fixtures["versions"].as_array().unwrap();
+
+    let find_project = |id: &str| {
+        projects
+            .iter()
+            .find(|p| p["id"] == id || p["slug"] == id)
+            .cloned()
+    };
+    let ids_param = || -> Vec<String> {
+        let ids = query
+            .split('&')
+            .find_map(|p| p.strip_prefix("ids="))
+            .unwrap_or("[]");
+        serde_json::from_str(ids).unwrap()
+    };
+
+    let found = match segments[..] {
+        ["files", name] => {
+            return match fs::read(fixture_path("files").join(name)) {
+                Ok(bytes) => ("200 OK", bytes),
+                Err(_) => ("404 Not Found", vec![]),
+            }
+        }
+        // the launcher's metadata, for loader versions
+        ["v1", uid, "index.json"] => fixtures["meta"].get(uid).cloned(),
+        ["v2", "project", id] => find_project(id),
+        ["v2", "project", id, "members"] => find_project(id).map(|_| fixtures["members"].clone()),
+        ["v2", "projects"] => Some(serde_json::Value::Array(
+            ids_param()
+                .iter()
+                .filter_map(|id| find_project(id))
+                .collect(),
+        )),
+        ["v2", "versions"] => {
+            let ids = ids_param();
+            Some(serde_json::Value::Array(
+                versions
+                    .iter()
+                    .filter(|v| ids.iter().any(|id| v["id"] == id.as_str()))
+                    .cloned()
+                    .collect(),
+            ))
+        }
+        ["v2", "version", id] => versions.iter().find(|v| v["id"] == id).cloned(),
+        ["v2", "version_file", hash] => versions
+            .iter()
+            .find(|v| v["files"][0]["hashes"]["sha512"] == hash)
+            .cloned(),
+        ["v2", "search"] => Some(serde_json::json!({
+            "hits": projects
+                .iter()
+                .map(|p| serde_json::json!({
+                    "project_id": p["id"],
+                    "slug": p["slug"],
+                    "title": p["title"],
+                    "description": p["description"],
+                    "project_type": p["project_type"],
+                    "author": "jellysquid3",
+                    "downloads": 1000,
+                }))
+                .collect::<Vec<serde_json::Value>>()
+        })),
+        _ => None,
+    };
+
+    match found {
+        Some(json) => ("200 OK", json.to_string().into_bytes()),
+        None => ("404 Not Found", b"{}".to_vec()),
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&input[i + 1..i + 3], 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(decoded).unwrap()
+}
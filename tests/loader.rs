@@ -0,0 +1,159 @@
+use modpm::loader::{
+    accepted_loaders, check_loader_version, compatible_loaders, fabric_range_allows, loader_rank,
+};
+use modpm::polymc::PolyInstance;
+use std::io::Write;
+
+fn instance(modloader: &str, game_version: &str) -> PolyInstance {
+    PolyInstance {
+        id: 1,
+        name: "Test Instance".to_string(),
+        folder_name: "test".to_string(),
+        game_version: game_version.to_string(),
+        modloader: modloader.to_string(),
+        modloader_version: None,
+    }
+}
+
+fn loaders(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn quilt_takes_fabric_mods_but_not_the_other_way_around() {
+    assert_eq!(
+        compatible_loaders("quilt", "1.20.4"),
+        loaders(&["quilt", "fabric"])
+    );
+    assert_eq!(compatible_loaders("fabric", "1.20.4"), loaders(&["fabric"]));
+
+    // quilt's own builds beat fabric ones
+    let accepted = accepted_loaders("mod", &instance("quilt", "1.20.1"));
+    assert_eq!(loader_rank(&loaders(&["quilt"]), &accepted), Some(0));
+    assert_eq!(loader_rank(&loaders(&["fabric"]), &accepted), Some(1));
+    assert_eq!(loader_rank(&loaders(&["forge"]), &accepted), None);
+}
+
+#[test]
+fn forge_and_neoforge_only_share_mods_on_1_20_1() {
+    assert_eq!(
+        compatible_loaders("neoforge", "1.20.1"),
+        loaders(&["neoforge", "forge"])
+    );
+    assert_eq!(
+        compatible_loaders("forge", "1.20.1"),
+        loaders(&["forge", "neoforge"])
+    );
+    assert_eq!(
+        compatible_loaders("neoforge", "1.20.4"),
+        loaders(&["neoforge"])
+    );
+    assert_eq!(compatible_loaders("forge", "1.19.2"), loaders(&["forge"]));
+}
+
+#[test]
+fn non_mods_ignore_the_modloader() {
+    let fabric = instance("fabric", "1.20.1");
+    assert_eq!(
+        accepted_loaders("resourcepack", &fabric),
+        loaders(&["minecraft"])
+    );
+    assert_eq!(
+        accepted_loaders("datapack", &fabric),
+        loaders(&["datapack"])
+    );
+    assert_eq!(
+        accepted_loaders("shader", &fabric),
+        loaders(&["iris", "vanilla"])
+    );
+    assert_eq!(
+        accepted_loaders("shader", &instance("forge", "1.20.1")),
+        loaders(&["optifine", "vanilla"])
+    );
+}
+
+#[test]
+fn fabric_ranges_follow_fabric_rules() {
+    // ^ only needs the same major version, unlike cargo's
+    assert!(fabric_range_allows("^0.14.0", "0.15.11"));
+    assert!(fabric_range_allows("^0.14.x", "0.16.5"));
+    assert!(!fabric_range_allows("^0.14.0", "0.13.9"));
+    assert!(!fabric_range_allows("^0.14.0", "1.0.0"));
+
+    // ~ needs the same minor version
+    assert!(fabric_range_allows("~0.14.10", "0.14.22"));
+    assert!(!fabric_range_allows("~0.14.10", "0.14.9"));
+    assert!(!fabric_range_allows("~0.14.10", "0.15.0"));
+
+    // spaces mean all of them
+    assert!(fabric_range_allows(">=0.14.21 <0.16", "0.15.11"));
+    assert!(!fabric_range_allows(">=0.14.21 <0.16", "0.16.0"));
+    assert!(!fabric_range_allows(">=0.14.21 <0.16", "0.14.20"));
+
+    assert!(fabric_range_allows("0.14.x", "0.14.22"));
+    assert!(!fabric_range_allows("0.14.x", "0.15.0"));
+    assert!(fabric_range_allows("0.14.21", "0.14.21"));
+    assert!(!fabric_range_allows("=0.14.21", "0.14.22"));
+    assert!(fabric_range_allows(">0.14.21", "0.14.22"));
+    assert!(fabric_range_allows("<=0.14.21", "0.14.21"));
+    assert!(fabric_range_allows("*", "0.1.0"));
+    assert!(fabric_range_allows(">=0.15.0", "0.15.0-beta.1"));
+}
+
+/// A jar with one metadata file in it.
+fn jar(name: &str, metadata_path: &str, metadata: &str) -> String {
+    let path = std::env::temp_dir().join(format!("modpm-loader-{}-{}", std::process::id(), name));
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    zip.start_file(metadata_path, zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(metadata.as_bytes()).unwrap();
+    zip.finish().unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn jars_say_which_loader_versions_they_need() {
+    let fabric = jar(
+        "fabric.jar",
+        "fabric.mod.json",
+        r#"{"depends": {"fabricloader": ">=0.15.0"}}"#,
+    );
+    assert!(check_loader_version(&fabric, "fabric", "0.15.3").is_ok());
+    // betas of a new enough version count
+    assert!(check_loader_version(&fabric, "fabric", "0.15.0-beta.1").is_ok());
+    assert_eq!(
+        check_loader_version(&fabric, "fabric", "0.14.21"),
+        Err(">=0.15.0".to_string())
+    );
+    // quilt reads its own metadata, and this jar doesn't have any
+    assert!(check_loader_version(&fabric, "quilt", "0.19.0").is_ok());
+
+    let quilt = jar(
+        "quilt.jar",
+        "quilt.mod.json",
+        r#"{"quilt_loader": {"depends": ["minecraft", {"id": "quilt_loader", "versions": ">=0.20.0"}]}}"#,
+    );
+    assert!(check_loader_version(&quilt, "quilt", "0.20.2").is_ok());
+    assert!(check_loader_version(&quilt, "quilt", "0.19.1").is_err());
+
+    let forge = jar(
+        "forge.jar",
+        "META-INF/mods.toml",
+        "[[dependencies.examplemod]]\nmodId = \"forge\"\nmandatory = true\nversionRange = \"[47.1,48)\"\n",
+    );
+    assert!(check_loader_version(&forge, "forge", "47.2.0").is_ok());
+    assert!(check_loader_version(&forge, "forge", "47.10").is_ok());
+    assert!(check_loader_version(&forge, "forge", "47.0.35").is_err());
+    assert!(check_loader_version(&forge, "forge", "48.0.1").is_err());
+
+    // not a zip at all
+    let not_a_jar =
+        std::env::temp_dir().join(format!("modpm-loader-{}-text.jar", std::process::id()));
+    std::fs::write(&not_a_jar, "hello").unwrap();
+    assert!(check_loader_version(not_a_jar.to_str().unwrap(), "fabric", "0.1.0").is_ok());
+
+    for path in [fabric, quilt, forge] {
+        std::fs::remove_file(path).ok();
+    }
+    std::fs::remove_file(not_a_jar).ok();
+}
@@ -0,0 +1,1575 @@
+mod common;
+
+use common::{FakeHome, MockModrinth};
+use modpm::cache::Cache;
+use modpm::client::ModrinthClient;
+use modpm::data_structs::{InstallReason, ModpmLockfile, LOCKFILE_SCHEMA_VERSION};
+use modpm::diff::LockfileDiff;
+use modpm::loader::latest_loader_version;
+use modpm::manifest::{Manifest, ManifestEntry};
+use modpm::modrinth::{DownloadOptions, ModVersion, MpmMod};
+use modpm::mrpack::Mrpack;
+use modpm::polymc::{MigrationMod, PolyInstance, PolyMC};
+use modpm::resolver::{OptionalDependencies, Resolver};
+use modpm::tree::DependencyTree;
+use modpm::{download_file, hash_file};
+use std::io::Write;
+
+const FABRIC: &str = "net.fabricmc.fabric-loader";
+
+async fn install(
+    client: &ModrinthClient,
+    instance: PolyInstance,
+    version: &str,
+    optional: OptionalDependencies,
+) {
+    let sodium = MpmMod::new(client, "sodium").await.unwrap();
+    sodium
+        .download(
+            client,
+            instance,
+            DownloadOptions {
+                version: Some(version.to_string()),
+                optional,
+                ..Default::default()
+            },
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn query_finds_a_mod_by_slug_and_by_hash() {
+    let server = MockModrinth::start();
+    let client = server.client();
+
+    let sodium = MpmMod::new(&client, "sodium").await.unwrap();
+    assert_eq!(sodium.title, "Sodium");
+    assert_eq!(sodium.id, "AANobbMI");
+    assert_eq!(sodium.versions.len(), 3);
+    assert_eq!(
+        sodium.get_owner().unwrap().user.display_name(),
+        "JellySquid"
+    );
+
+    assert_eq!(sodium.downloads, 48213397);
+    assert_eq!(sodium.followers, 21034);
+    assert_eq!(sodium.client_side, "required");
+    assert_eq!(sodium.server_side, "unsupported");
+    assert_eq!(sodium.categories, vec!["optimization"]);
+    assert_eq!(sodium.donation_urls[0].platform, "Ko-fi");
+
+    let numbers = |versions: Vec<ModVersion>| {
+        versions
+            .into_iter()
+            .map(|v| v.version_number)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        numbers(sodium.filter_versions(None, None)),
+        vec!["0.5.8", "0.5.0", "0.4.0"]
+    );
+    assert_eq!(
+        numbers(sodium.filter_versions(Some("1.20.1"), Some("fabric"))),
+        vec!["0.5.0", "0.4.0"]
+    );
+    assert!(sodium.filter_versions(None, Some("forge")).is_empty());
+
+    // projects without the newer fields still work
+    let indium = MpmMod::new(&client, "indium").await.unwrap();
+    assert_eq!(indium.downloads, 0);
+    assert_eq!(indium.client_side, "unknown");
+
+    assert!(MpmMod::new(&client, "not-a-mod").await.is_err());
+
+    let hash = hash_file(
+        common::fixture_path("files/sodium-0.5.0.jar")
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(MpmMod::new_from_hash(&client, &hash).await.id, "AANobbMI");
+}
+
+#[tokio::test]
+async fn rate_limited_requests_are_retried() {
+    let server = MockModrinth::start();
+    let client = ModrinthClient {
+        cache_ttl: None,
+        ..ModrinthClient::new(&format!("{}/v2/flaky", server.base))
+    };
+
+    let sodium = MpmMod::new(&client, "sodium").await.unwrap();
+    assert_eq!(sodium.title, "Sodium");
+}
+
+#[tokio::test]
+async fn search_returns_hits() {
+    let server = MockModrinth::start();
+
+    let hits = MpmMod::search(&server.client(), "sodium", None).await;
+    assert!(hits.iter().any(|h| h.slug == "sodium"));
+}
+
+#[tokio::test]
+async fn download_installs_required_dependencies() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let mods = home.mods_dir();
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+    assert!(mods.join("fabric-api-0.83.0.jar").exists());
+    assert!(!mods.join("indium-1.0.20.jar").exists());
+
+    let lockfile = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    assert_eq!(lockfile.len(), 2);
+    let fabric_api = lockfile
+        .iter()
+        .find(|m| m.project_id == "P7dR8mSH")
+        .unwrap();
+    assert_eq!(fabric_api.dependency_of(), Some("AANobbMI"));
+    assert_eq!(
+        hash_file(mods.join("fabric-api-0.83.0.jar").to_str().unwrap()).unwrap(),
+        fabric_api.hashes.sha512
+    );
+}
+
+#[tokio::test]
+async fn downloaded_jars_are_cached() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let sodium = ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .into_iter()
+        .find(|m| m.project_id == "AANobbMI")
+        .unwrap();
+    assert!(Cache::has_file(&sodium.hashes.sha512));
+    assert_eq!(Cache::info().files, 2);
+
+    // a second install comes straight out of the cache
+    std::fs::remove_file(home.mods_dir().join("sodium-0.5.0.jar")).unwrap();
+    assert!(Cache::link_file(
+        &sodium.hashes.sha512,
+        home.mods_dir().join("sodium-0.5.0.jar").to_str().unwrap()
+    ));
+    assert_eq!(
+        hash_file(home.mods_dir().join("sodium-0.5.0.jar").to_str().unwrap()).unwrap(),
+        sodium.hashes.sha512
+    );
+
+    // that's a hard link, so editing the jar edits the cached copy - which then gets thrown out
+    std::fs::write(home.mods_dir().join("sodium-0.5.0.jar"), "not a jar").unwrap();
+    assert!(!Cache::link_file(
+        &sodium.hashes.sha512,
+        home.mods_dir().join("other-sodium.jar").to_str().unwrap()
+    ));
+    assert!(!Cache::has_file(&sodium.hashes.sha512));
+}
+
+#[tokio::test]
+async fn hashes_that_are_not_sha512s_never_reach_the_cache_folder() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    // the cache keeps files under files/<first two characters>/<hash>, so this climbs out to
+    // the home folder
+    let victim = home.path.join("victim.txt");
+    std::fs::write(&victim, "don't delete me").unwrap();
+    let traversal = "../../victim.txt";
+    assert!(!Cache::has_file(traversal));
+    assert!(!Cache::link_file(
+        traversal,
+        home.path.join("linked.txt").to_str().unwrap()
+    ));
+    Cache::store_file(victim.to_str().unwrap(), traversal);
+    assert!(victim.exists());
+    assert!(!home.path.join("linked.txt").exists());
+    // and nothing panics on hashes that can't be cut where a real one would be
+    assert!(!Cache::has_file("aé"));
+    assert!(!Cache::has_file(&"A".repeat(128)));
+
+    // from a lockfile
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Skip,
+    )
+    .await;
+    let mut lockfile = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    for installed in lockfile.iter_mut() {
+        installed.hashes.sha512 = traversal.to_string();
+    }
+    ModpmLockfile::write_lockfile(home.instance(), &lockfile);
+    let output = home.modpm(&server, &["--yes", "sync", "-i", "test"]);
+    assert!(!output.status.success());
+    assert!(victim.exists());
+
+    // from a pack
+    let index = serde_json::json!({
+        "formatVersion": 1,
+        "game": "minecraft",
+        "versionId": "1.0.0",
+        "name": "Sneaky",
+        "files": [{
+            "path": "mods/sodium-0.5.0.jar",
+            "hashes": { "sha512": traversal },
+            "downloads": [format!("{}/files/sodium-0.5.0.jar", server.base)],
+        }],
+        "dependencies": { "minecraft": "1.20.1", "fabric-loader": "0.14.21" },
+    });
+    let pack_path = home.path.join("sneaky.mrpack");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&pack_path).unwrap());
+    zip.start_file(
+        "modrinth.index.json",
+        zip::write::SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.write_all(index.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+
+    let pack = Mrpack::open(pack_path.to_str().unwrap()).unwrap();
+    let instance = PolyMC::create_instance("Sneaky", "1.20.1", "fabric", Some("0.14.21")).unwrap();
+    assert!(pack.install(&client, &instance).await.is_err());
+    assert!(victim.exists());
+}
+
+#[tokio::test]
+async fn download_can_include_optional_dependencies() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+
+    assert!(home.mods_dir().join("indium-1.0.20.jar").exists());
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn reinstalling_a_mod_replaces_its_jar() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "0.4.0",
+        OptionalDependencies::Skip,
+    )
+    .await;
+    install(
+        &client,
+        home.instance(),
+        "0.5.0",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let mods = home.mods_dir();
+    assert!(!mods.join("sodium-0.4.0.jar").exists());
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn failed_downloads_keep_the_old_version() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    let lithium = MpmMod::new(&client, "lithium").await.unwrap();
+    lithium
+        .download(
+            &client,
+            home.instance(),
+            DownloadOptions {
+                version: Some("0.11.0".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+    // 0.11.1's jar isn't on the server
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "lithium",
+            "--version",
+            "0.11.1",
+            "-i",
+            "test",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Failed to download lithium-0.11.1.jar")
+    );
+
+    let mods = home.mods_dir();
+    assert!(mods.join("lithium-0.11.0.jar").exists());
+    assert!(!mods.join(".lithium-0.11.1.jar.part").exists());
+    let lockfile = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    assert_eq!(lockfile.len(), 1);
+    assert_eq!(lockfile[0].version_number, "0.11.0");
+}
+
+#[tokio::test]
+async fn downloads_work_without_a_content_length() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let expected = hash_file(
+        common::fixture_path("files/not-on-modrinth.jar")
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap();
+
+    download_file(
+        format!("{}/chunked/files/not-on-modrinth.jar", server.base),
+        home.mods_dir().to_str().unwrap().to_string(),
+        "not-on-modrinth.jar".to_string(),
+        &expected,
+    )
+    .await
+    .unwrap();
+
+    let downloaded = home.mods_dir().join("not-on-modrinth.jar");
+    assert_eq!(hash_file(downloaded.to_str().unwrap()).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn requested_mods_stay_requested_when_a_dependency_upgrades_them() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "0.4.0",
+        OptionalDependencies::Skip,
+    )
+    .await;
+    // sodium addon pins sodium 0.5.0
+    let addon = MpmMod::new(&client, "sodium-addon").await.unwrap();
+    addon
+        .download(
+            &client,
+            home.instance(),
+            DownloadOptions {
+                version: Some("latest".to_string()),
+                optional: OptionalDependencies::Skip,
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let sodium = ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .into_iter()
+        .find(|m| m.project_id == "AANobbMI")
+        .unwrap();
+    assert_eq!(sodium.version_number, "0.5.0");
+    assert_eq!(sodium.reason, InstallReason::Requested);
+}
+
+#[tokio::test]
+async fn mods_needing_a_newer_loader_are_refused() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+
+    // the instance has fabric loader 0.14.21, and lithium 0.12.0 wants 0.15
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "lithium",
+            "--version",
+            "0.12.0",
+            "-i",
+            "test",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("lithium-0.12.0.jar needs fabric >=0.15.0, but this instance has 0.14.21"));
+    assert!(!home.mods_dir().join("lithium-0.12.0.jar").exists());
+    assert!(ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .is_empty());
+
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "lithium",
+            "--version",
+            "0.11.0",
+            "-i",
+            "test",
+        ],
+    );
+    assert!(output.status.success());
+    assert!(home.mods_dir().join("lithium-0.11.0.jar").exists());
+}
+
+#[tokio::test]
+async fn data_packs_stay_in_one_world() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+    let minecraft = std::path::PathBuf::from(home.instance().minecraft_directory());
+    std::fs::create_dir_all(minecraft.join("saves/World A")).unwrap();
+    std::fs::create_dir_all(minecraft.join("saves/World B")).unwrap();
+
+    let afk_display = MpmMod::new(&client, "afk-display").await.unwrap();
+    assert_eq!(afk_display.project_type, "datapack");
+    afk_display
+        .download(
+            &client,
+            home.instance(),
+            DownloadOptions {
+                world: Some("World A".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+    let in_a = minecraft.join("saves/World A/datapacks/afk-display-1.1.zip");
+    assert!(in_a.exists());
+
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "afk-display",
+            "--world",
+            "World B",
+            "-i",
+            "test",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("is already installed in saves/World A/datapacks"));
+    assert!(in_a.exists());
+    assert!(!minecraft.join("saves/World B/datapacks").exists());
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        1
+    );
+
+    // a world that isn't there doesn't get made
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "afk-display",
+            "--world",
+            "Wrold A",
+            "-i",
+            "test",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = strip_colours(&String::from_utf8_lossy(&output.stderr));
+    assert!(
+        stderr.contains("doesn't have a world called Wrold A. Its worlds are: World A, World B")
+    );
+    assert!(!minecraft.join("saves/Wrold A").exists());
+}
+
+#[tokio::test]
+async fn picked_versions_have_to_fit_the_instance() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+
+    // 0.5.8 is only for 1.20.4
+    let output = home.modpm(
+        &server,
+        &[
+            "--yes",
+            "download",
+            "sodium",
+            "--version",
+            "0.5.8",
+            "-i",
+            "test",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "0.5.8 is for fabric on 1.20.4, which doesn't fit this instance (fabric 1.20.1)"
+    ));
+    assert!(ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn instances_are_only_found_by_exact_names_or_prefixes() {
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    home.add_instance("survival", "Survival World", FABRIC, "1.20.1");
+
+    assert_eq!(PolyMC::find_instance("test").unwrap().folder_name, "test");
+    assert_eq!(
+        PolyMC::find_instance("survival world").unwrap().folder_name,
+        "survival"
+    );
+    assert_eq!(
+        PolyMC::find_instance("surv").unwrap().folder_name,
+        "survival"
+    );
+
+    // close, but not close enough to act on
+    let error = PolyMC::find_instance("survivl").unwrap_err();
+    assert!(error.contains("Did you mean survival?"));
+    assert!(PolyMC::find_instance(".").is_err());
+    // the numbers move around when instances are added, so they don't count
+    assert!(PolyMC::find_instance("1").is_err());
+    assert!(PolyMC::find_instance("2").is_err());
+}
+
+/// The version of a fixture project that'd be picked for the test instance, to hand straight
+/// to the resolver.
+async fn latest(client: &ModrinthClient, project: &str) -> (String, ModVersion) {
+    let mpm_mod = MpmMod::new(client, project).await.unwrap();
+    let version = mpm_mod
+        .latest_compatible_version(&PolyMC::find_instance("test").unwrap())
+        .unwrap();
+    (mpm_mod.title, version)
+}
+
+async fn conflicts(resolver: Resolver<'_>, roots: Vec<(String, ModVersion)>) -> Vec<String> {
+    match resolver.resolve_all(roots).await {
+        Ok(_) => panic!("expected the resolver to find a conflict"),
+        Err(conflicts) => conflicts.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+#[tokio::test]
+async fn the_resolver_reports_conflicts() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+    let instance = home.instance();
+    let resolver = || Resolver::new(&client, &instance, OptionalDependencies::Skip);
+
+    // old lib only has a version for 1.19.2
+    assert_eq!(
+        conflicts(resolver(), vec![latest(&client, "legacy-hud").await]).await,
+        vec!["Old Lib is required by Legacy HUD, but has no version that supports this instance"]
+    );
+
+    assert_eq!(
+        conflicts(
+            resolver(),
+            vec![
+                latest(&client, "sodium").await,
+                latest(&client, "optifabric").await
+            ]
+        )
+        .await,
+        vec!["OptiFabric is incompatible with Sodium"]
+    );
+
+    // sodium glue pins sodium 0.4.0, but 0.5.0 was asked for
+    assert_eq!(
+        conflicts(
+            resolver(),
+            vec![
+                latest(&client, "sodium").await,
+                latest(&client, "sodium-glue").await
+            ]
+        )
+        .await,
+        vec!["Sodium is pinned to version 0.4.0, but version 0.5.0 was already picked"]
+    );
+
+    let (title, cycle_a) = latest(&client, "cycle-a").await;
+    let resolution = resolver().resolve(&title, cycle_a).await.unwrap();
+    assert_eq!(
+        resolution.cycles,
+        vec![vec!["Cycle A", "Cycle B", "Cycle A"]]
+    );
+    // the cycle is reported, not followed forever
+    assert_eq!(resolution.versions.len(), 2);
+}
+
+#[tokio::test]
+async fn installed_mods_can_rule_out_new_ones() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    let (_, optifabric) = latest(&client, "optifabric").await;
+    let file = optifabric.primary_file();
+    ModpmLockfile::add_to_lockfile(home.instance(), &optifabric, &file, "mod", "mods", None);
+    let lockfile_path = home.mods_dir().join(".modpm_lockfile.json");
+    let before = std::fs::read_to_string(&lockfile_path).unwrap();
+
+    let instance = home.instance();
+    assert_eq!(
+        conflicts(
+            Resolver::new(&client, &instance, OptionalDependencies::Skip),
+            vec![latest(&client, "sodium").await]
+        )
+        .await,
+        vec!["optifabric-1.0.jar is incompatible with Sodium"]
+    );
+
+    // and the real thing gives up without writing anything
+    let output = home.modpm(&server, &["--yes", "download", "sodium", "-i", "test"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("optifabric-1.0.jar is incompatible with Sodium"));
+    assert_eq!(std::fs::read_to_string(&lockfile_path).unwrap(), before);
+    assert!(!home.mods_dir().join("sodium-0.5.0.jar").exists());
+    assert!(!home.mods_dir().join("fabric-api-0.83.0.jar").exists());
+}
+
+/// Takes the colours back out of something printed with ansi_term.
+fn strip_colours(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+#[tokio::test]
+async fn instance_trees_start_from_requested_mods() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+
+    // indium needs sodium back, which used to hide sodium as a root
+    let tree = DependencyTree::for_instance(&client, &home.instance()).await;
+    assert_eq!(tree.roots, vec!["AANobbMI".to_string()]);
+    assert_eq!(
+        strip_colours(&tree.render()),
+        "Sodium (0.5.0)
+├ Fabric API (0.83.0+1.20.1)
+└ Indium (1.0.20+mc1.20.1) [optional]
+  └ Sodium (0.5.0) (*)
+"
+    );
+    assert_eq!(
+        strip_colours(&tree.render_reverse("P7dR8mSH")),
+        "Fabric API (0.83.0+1.20.1)
+└ Sodium (0.5.0)
+  └ Indium (1.0.20+mc1.20.1)
+    └ Sodium (0.5.0) [optional] (*)
+"
+    );
+
+    // a mod that isn't installed gets its tree from Modrinth
+    let legacy_hud = MpmMod::new(&client, "legacy-hud").await.unwrap();
+    let tree = DependencyTree::for_mod(&client, &legacy_hud, &home.instance()).await;
+    assert_eq!(
+        strip_colours(&tree.unwrap().render()),
+        "Legacy HUD (2.0)\n└ Old Lib\n"
+    );
+}
+
+#[tokio::test]
+async fn sync_makes_the_mods_folder_match_the_lockfile() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+
+    let mods = home.mods_dir();
+    std::fs::remove_file(mods.join("sodium-0.5.0.jar")).unwrap();
+    std::fs::write(mods.join("fabric-api-0.83.0.jar"), "not a jar").unwrap();
+    std::fs::write(mods.join("my-own-mod.jar"), "hi").unwrap();
+
+    // a teammate dropped indium from the shared lockfile
+    let mut lockfile = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    lockfile.retain(|m| m.project_id != "Orvt0mRa");
+    ModpmLockfile::write_lockfile(home.instance(), &lockfile);
+
+    let report = home.instance().sync(false).await;
+    assert_eq!(report.downloaded.len(), 2);
+    assert_eq!(report.removed, vec!["mods/indium-1.0.20.jar".to_string()]);
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+    assert!(!mods.join("indium-1.0.20.jar").exists());
+    assert!(mods.join("my-own-mod.jar").exists());
+    assert_eq!(
+        hash_file(mods.join("fabric-api-0.83.0.jar").to_str().unwrap()).unwrap(),
+        lockfile
+            .iter()
+            .find(|m| m.project_id == "P7dR8mSH")
+            .unwrap()
+            .hashes
+            .sha512
+    );
+
+    let report = home.instance().sync(true).await;
+    assert!(report.downloaded.is_empty());
+    assert_eq!(report.removed, vec!["mods/my-own-mod.jar".to_string()]);
+    assert_eq!(report.unchanged.len(), 2);
+}
+
+#[tokio::test]
+async fn verify_finds_and_repairs_changed_files() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+
+    install(
+        &server.client(),
+        home.instance(),
+        "latest",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let mods = home.mods_dir();
+    std::fs::remove_file(mods.join("sodium-0.5.0.jar")).unwrap();
+    std::fs::write(mods.join("fabric-api-0.83.0.jar"), "swapped").unwrap();
+    std::fs::write(mods.join("my-own-mod.jar"), "hi").unwrap();
+
+    let report = home.instance().verify(false).await;
+    assert_eq!(report.missing, vec!["mods/sodium-0.5.0.jar".to_string()]);
+    assert_eq!(
+        report.modified,
+        vec!["mods/fabric-api-0.83.0.jar".to_string()]
+    );
+    assert_eq!(report.untracked, vec!["mods/my-own-mod.jar".to_string()]);
+    assert!(report.repaired.is_empty());
+
+    let report = home.instance().verify(true).await;
+    let mut repaired = report.repaired.clone();
+    repaired.sort();
+    assert_eq!(
+        repaired,
+        vec![
+            "mods/fabric-api-0.83.0.jar".to_string(),
+            "mods/sodium-0.5.0.jar".to_string()
+        ]
+    );
+
+    let report = home.instance().verify(false).await;
+    assert_eq!(report.ok.len(), 2);
+    assert!(report.missing.is_empty() && report.modified.is_empty());
+    assert!(mods.join("my-own-mod.jar").exists());
+}
+
+#[tokio::test]
+async fn disabled_mods_stay_disabled() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "0.4.0",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let mods = home.mods_dir();
+    let disabled = home.instance().set_mod_enabled("sodium", false).unwrap();
+    assert!(disabled.disabled);
+    assert!(!mods.join("sodium-0.4.0.jar").exists());
+    assert!(mods.join("sodium-0.4.0.jar.disabled").exists());
+
+    // verify and sync know where disabled mods are
+    let report = home.instance().verify(false).await;
+    assert_eq!(report.ok.len(), 2);
+    assert!(report.untracked.is_empty());
+    let report = home.instance().sync(true).await;
+    assert!(report.downloaded.is_empty() && report.removed.is_empty());
+
+    let mut manifest = Manifest::load(&home.instance()).unwrap();
+    manifest.mods.get_mut("sodium").unwrap().version = None;
+    manifest.save(&home.instance());
+    home.instance().update(&client).await.unwrap();
+    assert!(mods.join("sodium-0.5.0.jar.disabled").exists());
+    assert!(!mods.join("sodium-0.4.0.jar.disabled").exists());
+
+    home.instance().set_mod_enabled("sodium", true).unwrap();
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+    assert!(home
+        .instance()
+        .set_mod_enabled("not-installed", false)
+        .is_err());
+
+    // if the file can't be renamed, the lockfile doesn't pretend it was
+    std::fs::remove_file(mods.join("sodium-0.5.0.jar")).unwrap();
+    assert!(home.instance().set_mod_enabled("sodium", false).is_err());
+    assert!(ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .iter()
+        .any(|m| m.project_id == "AANobbMI" && !m.disabled));
+}
+
+#[tokio::test]
+async fn copy_installs_versions_for_the_other_instance() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+    home.instance()
+        .set_mod_enabled("fabric-api", false)
+        .unwrap();
+    let newer = home.add_instance("newer", "Newer", FABRIC, "1.20.4");
+
+    let report = newer.copy_mods_from(&client, &home.instance()).await;
+    let mut installed = report.installed.clone();
+    installed.sort();
+    // fabric api only came along as a dependency, but it still gets its title
+    assert_eq!(
+        installed,
+        vec!["Fabric API".to_string(), "Sodium".to_string()]
+    );
+    assert!(report.problems.is_empty());
+
+    let lockfile = ModpmLockfile::get_lockfile(newer.clone()).unwrap();
+    let filenames: Vec<&str> = lockfile.iter().map(|m| &m.filename[..]).collect();
+    assert!(filenames.contains(&"sodium-0.5.8.jar"));
+    assert!(filenames.contains(&"fabric-api-0.91.0.jar"));
+    // indium has nothing for 1.20.4, and it was only there as an optional dependency
+    assert_eq!(lockfile.len(), 2);
+    assert!(lockfile
+        .iter()
+        .any(|m| m.project_id == "P7dR8mSH" && m.disabled));
+    assert!(Manifest::load(&newer).unwrap().mods.contains_key("sodium"));
+
+    // copying back the other way finds the mod that doesn't exist there
+    let older = home.add_instance("older", "Older", FABRIC, "1.19.2");
+    let report = older.copy_mods_from(&client, &newer).await;
+    assert_eq!(report.missing, vec!["Sodium".to_string()]);
+    assert!(ModpmLockfile::get_lockfile(older).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn json_output_is_just_json() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+    home.add_instance("newer", "Newer", FABRIC, "1.20.4");
+
+    // the resolver and the downloads talk while this runs, but that has to stay off stdout
+    let output = home.modpm(&server, &["--json", "copy", "test", "newer"]);
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(report["installed"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!("Sodium")));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Downloading"));
+
+    // --yes answers prompts out loud, and commands that have nothing to say in JSON say nothing
+    let output = home.modpm(
+        &server,
+        &["--json", "--yes", "download", "lithium", "-i", "test"],
+    );
+    // the latest Lithium needs a newer loader, so the refusal has to land on stderr too
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("What version of this mod do you want to download? 3"));
+    assert!(stderr.contains("needs fabric >=0.15.0"));
+    let output = home.modpm(&server, &["--json", "cache", "clean"]);
+    assert!(output.stdout.is_empty());
+}
+
+#[tokio::test]
+async fn diff_shows_added_removed_and_changed_mods() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+    let newer = home.add_instance("newer", "Newer", FABRIC, "1.20.4");
+    newer.copy_mods_from(&client, &home.instance()).await;
+
+    let old = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    let mut diff = LockfileDiff::between(&old, &ModpmLockfile::get_lockfile(newer).unwrap());
+    diff.fill_titles(&client).await;
+
+    assert!(diff.added.is_empty());
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].title, "Indium");
+    assert_eq!(
+        diff.removed[0].old_version.as_deref(),
+        Some("1.0.20+mc1.20.1")
+    );
+    let changed: Vec<(&str, &str, &str)> = diff
+        .changed
+        .iter()
+        .map(|e| {
+            (
+                &e.title[..],
+                e.old_version.as_deref().unwrap(),
+                e.new_version.as_deref().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        changed,
+        vec![
+            ("Fabric API", "0.83.0+1.20.1", "0.91.0+1.20.4"),
+            ("Sodium", "0.5.0", "0.5.8")
+        ]
+    );
+
+    // an old lockfile on its own, the way it'd come out of git
+    let from_file = ModpmLockfile::read_lockfile_file(
+        common::fixture_path("lockfile_v0.json").to_str().unwrap(),
+    )
+    .unwrap();
+    let diff = LockfileDiff::between(&from_file, &old);
+    assert!(diff.changed.is_empty());
+    assert_eq!(diff.added.len(), 1);
+    assert!(LockfileDiff::between(&old, &old).is_empty());
+
+    // broken lockfiles are an error, not a panic
+    assert!(ModpmLockfile::parse_lockfile("not json").is_err());
+    assert!(ModpmLockfile::parse_lockfile(r#"{"schema_version": 99, "mods": []}"#).is_err());
+    let broken = home.path.join("broken.json");
+    std::fs::write(&broken, "{\"mods\": 3}").unwrap();
+    let output = home.modpm(&server, &["diff", broken.to_str().unwrap(), "test"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("broken.json") && !stderr.contains("panicked"));
+    assert!(stderr.contains("Couldn't read the old side"));
+
+    // and so is a broken lockfile in an instance, which says which side and which file it was
+    let lockfile_path = ModpmLockfile::get_path(&home.instance());
+    std::fs::write(&lockfile_path, "not json").unwrap();
+    assert!(ModpmLockfile::get_lockfile(home.instance()).is_err());
+    let output = home.modpm(&server, &["diff", "newer", "test"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Couldn't read the new side"));
+    assert!(stderr.contains(&lockfile_path) && !stderr.contains("panicked"));
+}
+
+#[tokio::test]
+async fn migration_check_reports_and_applies() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+
+    let report = home.instance().migration_check(&client, "1.20.4").await;
+    let titles = |mods: &[MigrationMod]| mods.iter().map(|m| m.title.clone()).collect::<Vec<_>>();
+    assert_eq!(titles(&report.ready), vec!["Sodium"]);
+    assert_eq!(report.ready[0].available.as_deref(), Some("0.5.8"));
+    assert_eq!(titles(&report.only_beta), vec!["Fabric API"]);
+    assert_eq!(titles(&report.missing), vec!["Indium"]);
+    // indium is only an optional dependency, so it can just be left out
+    assert!(report.can_migrate());
+
+    let report = home.instance().migration_check(&client, "1.19.2").await;
+    assert!(!report.can_migrate());
+    assert!(report
+        .missing
+        .iter()
+        .any(|m| m.title == "Sodium" && m.blocking));
+
+    // going ahead anyway fails before anything's downloaded, and leaves the instance where it was
+    assert!(home.instance().migrate(&client, "1.19.2").await.is_err());
+    assert_eq!(home.instance().game_version, "1.20.1");
+
+    let migrated = home.instance().migrate(&client, "1.20.4").await.unwrap();
+    assert_eq!(migrated.game_version, "1.20.4");
+    assert_eq!(migrated.modloader, "fabric");
+    assert_eq!(home.instance().game_version, "1.20.4");
+
+    let mods = home.mods_dir();
+    assert!(mods.join("sodium-0.5.8.jar").exists());
+    assert!(mods.join("fabric-api-0.91.0.jar").exists());
+    assert!(!mods.join("indium-1.0.20.jar").exists());
+}
+
+#[tokio::test]
+async fn migrations_that_fail_partway_put_everything_back() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Skip,
+    )
+    .await;
+    // Sodium Addon has a build for 1.20.4, but its file isn't there to download
+    let addon = MpmMod::new(&client, "sodium-addon").await.unwrap();
+    addon
+        .download(
+            &client,
+            home.instance(),
+            DownloadOptions {
+                version: Some("latest".to_string()),
+                optional: OptionalDependencies::Skip,
+                ..Default::default()
+            },
+        )
+        .await;
+
+    let mods = home.mods_dir();
+    let snapshot = || {
+        let mut files: Vec<(String, String)> = std::fs::read_dir(&mods)
+            .unwrap()
+            .flatten()
+            .map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                let contents = hash_file(e.path().to_str().unwrap()).unwrap();
+                (name, contents)
+            })
+            .collect();
+        files.sort();
+        files
+    };
+    let before = snapshot();
+    let minecraft = std::path::PathBuf::from(home.instance().minecraft_directory());
+    let lockfile = std::fs::read_to_string(ModpmLockfile::get_path(&home.instance())).unwrap();
+
+    let output = home.modpm(
+        &server,
+        &["--yes", "migrate-check", "test", "1.20.4", "--apply"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Downloading sodium-0.5.8.jar"));
+    assert!(stderr.contains("Failed to download sodium-addon-1.1.jar"));
+    assert!(stderr.contains("The instance is still on 1.20.1."));
+
+    assert_eq!(home.instance().game_version, "1.20.1");
+    assert_eq!(
+        std::fs::read_to_string(ModpmLockfile::get_path(&home.instance())).unwrap(),
+        lockfile
+    );
+    assert_eq!(snapshot(), before);
+    assert!(!minecraft.join(".modpm_backup").exists());
+}
+
+#[tokio::test]
+async fn migration_check_reports_mods_it_cant_fetch() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    let mut version = MpmMod::new(&client, "sodium").await.unwrap().versions[0].clone();
+    version.project_id = "gone-from-modrinth".to_string();
+    let file = version.primary_file();
+    ModpmLockfile::add_to_lockfile(home.instance(), &version, &file, "mod", "mods", None);
+
+    let report = home.instance().migration_check(&client, "1.20.4").await;
+    assert_eq!(report.problems.len(), 1);
+    assert!(report.problems[0].contains(&file.filename));
+    assert!(!report.can_migrate());
+}
+
+#[tokio::test]
+async fn instances_can_be_created() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+
+    let instance =
+        PolyMC::create_instance("My Pack!", "1.20.1", "fabric", Some("0.14.21")).unwrap();
+    assert_eq!(instance.name, "My Pack!");
+    assert_eq!(instance.folder_name, "My Pack");
+    assert_eq!(instance.modloader, "fabric");
+    assert_eq!(instance.modloader_version.as_deref(), Some("0.14.21"));
+    assert_eq!(instance.game_version, "1.20.1");
+
+    let mmc_pack: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(
+            home.path
+                .join(".local/share/PolyMC/instances/My Pack/mmc-pack.json"),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        mmc_pack["components"][1]["uid"],
+        "net.fabricmc.intermediary"
+    );
+
+    // same name again gets its own folder
+    let again = PolyMC::create_instance("My Pack!", "1.20.1", "vanilla", None).unwrap();
+    assert_eq!(again.folder_name, "My Pack2");
+    assert_eq!(again.modloader, "vanilla");
+    assert!(PolyMC::create_instance("Nope", "1.20.1", "fabric", None).is_err());
+
+    let meta = format!("{}/v1", server.base);
+    assert_eq!(
+        latest_loader_version(&meta, "fabric", "1.20.1")
+            .await
+            .unwrap(),
+        "0.14.21"
+    );
+    assert_eq!(
+        latest_loader_version(&meta, "forge", "1.20.1")
+            .await
+            .unwrap(),
+        "47.2.1"
+    );
+    assert!(latest_loader_version(&meta, "forge", "1.19.2")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn mrpacks_seed_new_instances() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    let sha512 =
+        |name: &str| hash_file(common::fixture_path("files").join(name).to_str().unwrap()).unwrap();
+    let index = serde_json::json!({
+        "formatVersion": 1,
+        "game": "minecraft",
+        "versionId": "1.0.0",
+        "name": "Speedy",
+        "files": [
+            {
+                "path": "mods/sodium-0.5.0.jar",
+                "hashes": { "sha512": sha512("sodium-0.5.0.jar") },
+                "downloads": [format!("{}/files/sodium-0.5.0.jar", server.base)],
+            },
+            {
+                "path": "mods/not-on-modrinth.jar",
+                "hashes": { "sha512": sha512("not-on-modrinth.jar") },
+                "env": { "client": "required", "server": "required" },
+                "downloads": [format!("{}/files/not-on-modrinth.jar", server.base)],
+            },
+            {
+                "path": "mods/server-only.jar",
+                "hashes": { "sha512": "0" },
+                "env": { "client": "unsupported", "server": "required" },
+                "downloads": [],
+            },
+        ],
+        "dependencies": { "minecraft": "1.20.1", "fabric-loader": "0.14.21" },
+    });
+
+    let pack_path = home.path.join("speedy.mrpack");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&pack_path).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("modrinth.index.json", options).unwrap();
+    zip.write_all(index.to_string().as_bytes()).unwrap();
+    zip.start_file("overrides/config/sodium-options.json", options)
+        .unwrap();
+    zip.write_all(b"{}").unwrap();
+    zip.finish().unwrap();
+
+    let pack = Mrpack::open(pack_path.to_str().unwrap()).unwrap();
+    assert_eq!(pack.game_version(), Some("1.20.1"));
+    assert_eq!(
+        pack.loader(),
+        ("fabric".to_string(), Some("0.14.21".to_string()))
+    );
+
+    let instance =
+        PolyMC::create_instance(&pack.index.name, "1.20.1", "fabric", Some("0.14.21")).unwrap();
+    pack.install(&client, &instance).await.unwrap();
+
+    let minecraft = std::path::PathBuf::from(instance.minecraft_directory());
+    assert!(minecraft.join("mods/sodium-0.5.0.jar").exists());
+    // only what's in the pack, nothing worked out on top of it
+    assert!(!minecraft.join("mods/fabric-api-0.83.0.jar").exists());
+    assert!(minecraft.join("mods/not-on-modrinth.jar").exists());
+    assert!(!minecraft.join("mods/server-only.jar").exists());
+    assert!(minecraft.join("config/sodium-options.json").exists());
+
+    // the pack's exact version is pinned, so update won't move it
+    assert_eq!(
+        Manifest::load(&instance).unwrap().mods["sodium"]
+            .version
+            .as_deref(),
+        Some("YL57xq9U")
+    );
+    let lockfile = ModpmLockfile::get_lockfile(instance).unwrap();
+    assert_eq!(lockfile.len(), 1);
+    assert_eq!(lockfile[0].version_id, "YL57xq9U");
+    assert_eq!(lockfile[0].hashes.sha512, sha512("sodium-0.5.0.jar"));
+
+    // a --loader-version wins over the pack's
+    let output = home.modpm(
+        &server,
+        &[
+            "--json",
+            "instance",
+            "create",
+            "--mrpack",
+            pack_path.to_str().unwrap(),
+            "--loader-version",
+            "0.15.0",
+        ],
+    );
+    assert!(output.status.success());
+    let created: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(created["modloader_version"], "0.15.0");
+}
+
+#[tokio::test]
+async fn failed_instance_creation_leaves_nothing_behind() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let instances = home.path.join(".local/share/PolyMC/instances");
+    let count = || std::fs::read_dir(&instances).unwrap().count();
+    let before = count();
+
+    // instance.cfg can't be given settings of its own through the name
+    assert!(
+        PolyMC::create_instance("Sneaky\nInstanceType=Legacy", "1.20.1", "vanilla", None).is_err()
+    );
+    assert!(PolyMC::create_instance("Tab\tName", "1.20.1", "vanilla", None).is_err());
+    assert_eq!(count(), before);
+
+    // a manifest whose mods can't be installed
+    let manifest = home.path.join("modpm.toml");
+    std::fs::write(&manifest, "[mods]\nnot-on-modrinth = {}\n").unwrap();
+    let output = home.modpm(
+        &server,
+        &[
+            "instance",
+            "create",
+            "--name",
+            "Broken",
+            "--game",
+            "1.20.1",
+            "--loader",
+            "fabric",
+            "--loader-version",
+            "0.14.21",
+            "--manifest",
+            manifest.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not-on-modrinth in modpm.toml isn't on Modrinth."));
+    assert!(stderr.contains("I deleted"));
+    assert!(!instances.join("Broken").exists());
+
+    // a pack with a file that won't download
+    let index = serde_json::json!({
+        "formatVersion": 1,
+        "game": "minecraft",
+        "versionId": "1.0.0",
+        "name": "Unlucky",
+        "files": [{
+            "path": "mods/gone.jar",
+            "hashes": { "sha512": "0".repeat(128) },
+            "downloads": [format!("{}/files/gone.jar", server.base)],
+        }],
+        "dependencies": { "minecraft": "1.20.1", "fabric-loader": "0.14.21" },
+    });
+    let pack_path = home.path.join("unlucky.mrpack");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&pack_path).unwrap());
+    zip.start_file(
+        "modrinth.index.json",
+        zip::write::SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.write_all(index.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+
+    let output = home.modpm(
+        &server,
+        &[
+            "instance",
+            "create",
+            "--mrpack",
+            pack_path.to_str().unwrap(),
+        ],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to download") && stderr.contains("I deleted"));
+    assert!(!instances.join("Unlucky").exists());
+    assert_eq!(count(), before);
+}
+
+#[tokio::test]
+async fn outdated_and_update_see_newer_versions() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "0.4.0",
+        OptionalDependencies::Skip,
+    )
+    .await;
+
+    let outdated = home.instance().outdated(&client).await;
+    assert_eq!(outdated.len(), 1);
+    assert_eq!(outdated[0].installed.version_number, "0.4.0");
+    assert_eq!(outdated[0].latest.version_number, "0.5.0");
+
+    // picking an older version pins it in the manifest, so update leaves it alone
+    home.instance().update(&client).await.unwrap();
+    assert!(home.mods_dir().join("sodium-0.4.0.jar").exists());
+
+    let mut manifest = Manifest::load(&home.instance()).unwrap();
+    manifest.mods.get_mut("sodium").unwrap().version = None;
+    manifest.save(&home.instance());
+
+    home.instance().update(&client).await.unwrap();
+    assert!(!home.mods_dir().join("sodium-0.4.0.jar").exists());
+    assert!(home.mods_dir().join("sodium-0.5.0.jar").exists());
+    assert!(home.instance().outdated(&client).await.is_empty());
+}
+
+#[tokio::test]
+async fn update_follows_manifest_constraints() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+
+    install(
+        &client,
+        home.instance(),
+        "latest",
+        OptionalDependencies::Install,
+    )
+    .await;
+    assert_eq!(
+        Manifest::load(&home.instance()).unwrap().mods["sodium"],
+        ManifestEntry::default()
+    );
+
+    // 0.5.0 is a beta, and it's the only version that wants indium
+    std::fs::write(
+        Manifest::get_path(&home.instance()),
+        "[mods.sodium]\nchannel = \"release\"\n",
+    )
+    .unwrap();
+    home.instance().update(&client).await.unwrap();
+
+    let mods = home.mods_dir();
+    assert!(mods.join("sodium-0.4.0.jar").exists());
+    assert!(!mods.join("sodium-0.5.0.jar").exists());
+    assert!(!mods.join("indium-1.0.20.jar").exists());
+    assert!(mods.join("fabric-api-0.83.0.jar").exists());
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        2
+    );
+
+    std::fs::write(
+        Manifest::get_path(&home.instance()),
+        "[mods.sodium]\nversion = \">=0.5\"\n",
+    )
+    .unwrap();
+    home.instance().update(&client).await.unwrap();
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+}
+
+#[test]
+fn old_lockfiles_are_migrated() {
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let lockfile_path = home.mods_dir().join(".modpm_lockfile.json");
+    std::fs::copy(common::fixture_path("lockfile_v0.json"), &lockfile_path).unwrap();
+
+    let lockfile = ModpmLockfile::get_lockfile(home.instance()).unwrap();
+    assert_eq!(lockfile.len(), 2);
+    assert_eq!(lockfile[0].project_id, "AANobbMI");
+    assert_eq!(lockfile[0].version_id, "YL57xq9U");
+    assert_eq!(lockfile[0].filename, "sodium-0.5.0.jar");
+    assert_eq!(lockfile[0].reason, InstallReason::Requested);
+    assert_eq!(lockfile[0].directory, "mods");
+
+    // reading leaves the file alone, the next write upgrades it
+    assert!(std::fs::read_to_string(&lockfile_path)
+        .unwrap()
+        .starts_with('['));
+    ModpmLockfile::remove_from_lockfile(home.instance(), "P7dR8mSH");
+
+    let written: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&lockfile_path).unwrap()).unwrap();
+    assert_eq!(written["schema_version"], LOCKFILE_SCHEMA_VERSION);
+    assert_eq!(written["mods"][0]["version_id"], "YL57xq9U");
+    assert!(written["mods"][0].get("mpm_mod").is_none());
+}
+
+#[tokio::test]
+async fn concurrent_lockfile_writes_are_not_lost() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let sodium = MpmMod::new(&server.client(), "sodium").await.unwrap();
+    let version = sodium.versions[0].clone();
+
+    let threads: Vec<_> = (0..8)
+        .map(|n| {
+            let instance = home.instance();
+            let mut version = version.clone();
+            version.project_id = format!("project-{}", n);
+            std::thread::spawn(move || {
+                let file = version.primary_file();
+                ModpmLockfile::add_to_lockfile(instance, &version, &file, "mod", "mods", None);
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        8
+    );
+    let leftovers: Vec<_> = std::fs::read_dir(home.mods_dir())
+        .unwrap()
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty());
+}
+
+#[tokio::test]
+async fn commands_hold_the_instance_lock_the_whole_time() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new(FABRIC, "1.20.1");
+    let client = server.client();
+    let lock_path = home.mods_dir().join(".modpm.lock");
+
+    let writer = ModpmLockfile::with_instance_locked(&home.instance(), async {
+        // another modpm can't get in between the steps...
+        let other = std::fs::File::open(&lock_path).unwrap();
+        assert!(other.try_lock().is_err());
+
+        // ...but everything this one does while it has the lock still goes through
+        install(
+            &client,
+            home.instance(),
+            "latest",
+            OptionalDependencies::Skip,
+        )
+        .await;
+        home.instance().update(&client).await.unwrap();
+        assert_eq!(
+            ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+            2
+        );
+        assert!(other.try_lock().is_err());
+
+        // a thread that isn't part of this command has to wait until it's over
+        let instance = home.instance();
+        let writer = std::thread::spawn(move || ModpmLockfile::write_lockfile(instance, &[]));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!writer.is_finished());
+        assert_eq!(
+            ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+            2
+        );
+        writer
+    })
+    .await;
+
+    writer.join().unwrap();
+    assert!(ModpmLockfile::get_lockfile(home.instance())
+        .unwrap()
+        .is_empty());
+    let other = std::fs::File::open(&lock_path).unwrap();
+    assert!(other.try_lock().is_ok());
+}
+
+#[tokio::test]
+async fn quilt_instances_take_fabric_mods() {
+    let server = MockModrinth::start();
+    let home = FakeHome::new("org.quiltmc.quilt-loader", "1.20.1");
+
+    let sodium = MpmMod::new(&server.client(), "sodium").await.unwrap();
+    let latest = sodium.latest_compatible_version(&home.instance()).unwrap();
+    assert_eq!(latest.version_number, "0.5.0");
+}
@@ -0,0 +1,96 @@
+//! Offline mode is a global switch, so these get their own test binary.
+
+mod common;
+
+use common::{FakeHome, MockModrinth};
+use modpm::client::ModrinthClient;
+use modpm::data_structs::ModpmLockfile;
+use modpm::http;
+use modpm::modrinth::{DownloadOptions, MpmMod};
+use modpm::resolver::OptionalDependencies;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// the tests in here run at the same time, and offline mode is for everyone
+static OFFLINE_SWITCH: Mutex<()> = Mutex::const_new(());
+
+#[tokio::test]
+async fn installs_work_offline_once_cached() {
+    let _switch = OFFLINE_SWITCH.lock().await;
+    let server = MockModrinth::start();
+    let home = FakeHome::new("net.fabricmc.fabric-loader", "1.20.1");
+    // unlike `server.client()`, this one reuses cached API responses
+    let client = ModrinthClient::new(&format!("{}/v2", server.base));
+
+    let options = || DownloadOptions {
+        version: Some("latest".to_string()),
+        optional: OptionalDependencies::Skip,
+        ..Default::default()
+    };
+
+    let sodium = MpmMod::new(&client, "sodium").await.unwrap();
+    sodium.download(&client, home.instance(), options()).await;
+
+    let mods = home.mods_dir();
+    for installed in ModpmLockfile::get_lockfile(home.instance()).unwrap() {
+        ModpmLockfile::remove_from_lockfile(home.instance(), &installed.project_id);
+        std::fs::remove_file(mods.join(&installed.filename)).unwrap();
+    }
+
+    http::set_offline(true);
+
+    let sodium = MpmMod::new(&client, "sodium").await.unwrap();
+    sodium.download(&client, home.instance(), options()).await;
+
+    assert!(mods.join("sodium-0.5.0.jar").exists());
+    assert!(mods.join("fabric-api-0.83.0.jar").exists());
+    assert_eq!(
+        ModpmLockfile::get_lockfile(home.instance()).unwrap().len(),
+        2
+    );
+
+    assert!(client.get("project/indium").await.is_err());
+
+    http::set_offline(false);
+}
+
+#[tokio::test]
+async fn offline_mode_uses_stale_cache_entries() {
+    let _switch = OFFLINE_SWITCH.lock().await;
+    let server = MockModrinth::start();
+    let _home = FakeHome::new("net.fabricmc.fabric-loader", "1.20.1");
+    let mut client = ModrinthClient::new(&format!("{}/v2", server.base));
+    client.cache_ttl = Some(Duration::from_millis(1));
+
+    client.get("project/sodium").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    http::set_offline(true);
+    // too old to use online, but offline it's all there is
+    let stale = client.get("project/sodium").await;
+    // and that goes for a client with the cache turned off too, like cache_ttl = 0
+    client.cache_ttl = None;
+    let uncached = client.get("project/sodium").await;
+    http::set_offline(false);
+
+    assert!(stale.unwrap().contains("Sodium"));
+    assert!(uncached.unwrap().contains("Sodium"));
+}
+
+#[tokio::test]
+async fn responses_are_kept_for_offline_even_with_the_cache_ttl_off() {
+    let _switch = OFFLINE_SWITCH.lock().await;
+    let server = MockModrinth::start();
+    let _home = FakeHome::new("net.fabricmc.fabric-loader", "1.20.1");
+    // like `cache_ttl = 0` in the config
+    let client = server.client();
+    assert_eq!(client.cache_ttl, None);
+
+    client.get("project/sodium").await.unwrap();
+
+    http::set_offline(true);
+    let offline = client.get("project/sodium").await;
+    http::set_offline(false);
+
+    assert!(offline.unwrap().contains("Sodium"));
+}